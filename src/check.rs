@@ -0,0 +1,236 @@
+// link checker for a built site: every href and src in the html pages in outdir
+// must name a file in outdir (under prefix for absolute paths), and a fragment
+// must name an id on the page it points to
+use anyhow::bail;
+use kuchikiki::traits::TendrilSink;
+use percent_encoding::percent_decode_str;
+use rayon::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::{config::Config, util::NodeRefExt};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    // no file in outdir
+    Missing,
+    // the page exists, the id doesn't
+    MissingAnchor(String),
+    // an absolute path that doesn't start with prefix
+    OutsidePrefix,
+    // a relative path that climbs out of outdir
+    OutsideOutdir,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Missing => write!(f, "not found"),
+            Problem::MissingAnchor(id) => write!(f, "no element with id '{}'", id),
+            Problem::OutsidePrefix => write!(f, "outside prefix"),
+            Problem::OutsideOutdir => write!(f, "outside outdir"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DanglingLink {
+    // page the link is on, relative to outdir
+    pub page: PathBuf,
+    // href or src
+    pub attr: String,
+    pub target: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for DanglingLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}=\"{}\": {}", self.page.display(), self.attr, self.target, self.problem)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub pages: usize,
+    pub links: usize,
+    pub dangling: Vec<DanglingLink>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for link in &self.dangling {
+            writeln!(f, "{}", link)?;
+        }
+        write!(f, "checked {} links on {} pages: {} dangling", self.links, self.pages, self.dangling.len())
+    }
+}
+
+// what the checker needs from a page: its links and the ids it can be linked to
+struct Page {
+    links: Vec<(String, String)>,
+    ids: BTreeSet<String>,
+}
+
+pub fn check(config: &Config) -> anyhow::Result<Report> {
+    if !config.outdir.is_dir() {
+        bail!("outdir {} not found, build the site first", config.outdir.display());
+    }
+    let mut files = BTreeSet::new();
+    for entry in WalkDir::new(&config.outdir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.insert(entry.path().strip_prefix(&config.outdir)?.to_path_buf());
+        }
+    }
+    let html: Vec<&PathBuf> = files.iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "html" || ext == "htm"))
+        .collect();
+    let pages: BTreeMap<PathBuf, Page> = html.par_iter()
+        .map(|path| {
+            let text = std::fs::read_to_string(config.outdir.join(path))?;
+            let document = kuchikiki::parse_html().one(text);
+            Ok((path.to_path_buf(), Page { links: document.link_targets(), ids: document.anchor_ids() }))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut report = Report { pages: pages.len(), ..Report::default() };
+    for (page_path, page) in &pages {
+        for (attr, target) in &page.links {
+            report.links += 1;
+            let problem = match resolve(config, page_path, target) {
+                Resolved::External => None,
+                Resolved::Problem(problem) => Some(problem),
+                Resolved::File(path, fragment) => {
+                    let path = path.unwrap_or_else(|| page_path.clone());
+                    match find_file(&files, &path) {
+                        None => Some(Problem::Missing),
+                        Some(found) => fragment
+                            .filter(|id| pages.get(&found).is_some_and(|target| !target.ids.contains(id)))
+                            .map(Problem::MissingAnchor),
+                    }
+                },
+            };
+            if let Some(problem) = problem {
+                report.dangling.push(DanglingLink {
+                    page: page_path.clone(),
+                    attr: attr.clone(),
+                    target: target.clone(),
+                    problem,
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+enum Resolved {
+    // another site, mailto:, data: and the like
+    External,
+    // path relative to outdir, none for the page itself; fragment
+    File(Option<PathBuf>, Option<String>),
+    Problem(Problem),
+}
+
+fn resolve(config: &Config, page: &Path, target: &str) -> Resolved {
+    let target = target.trim();
+    let is_url = target.split('/').next().is_some_and(|first| first.contains(':'));
+    if target.is_empty() || is_url || target.starts_with("//") {
+        return Resolved::External;
+    }
+    let (target, fragment) = match target.split_once('#') {
+        Some((target, fragment)) => (target, Some(fragment)),
+        None => (target, None),
+    };
+    let fragment = fragment.filter(|fragment| !fragment.is_empty() && *fragment != "top")
+        .map(|fragment| percent_decode_str(fragment).decode_utf8_lossy().to_string());
+    let path = target.split('?').next().unwrap_or("");
+    if path.is_empty() {
+        return Resolved::File(None, fragment);
+    }
+    let path = percent_decode_str(path).decode_utf8_lossy().to_string();
+    let joined = match path.strip_prefix('/') {
+        Some(rooted) => {
+            let prefix = config.prefix.trim_start_matches('/');
+            match rooted.strip_prefix(prefix) {
+                Some(rel_path) => PathBuf::from(rel_path),
+                // the prefix without its trailing /
+                None if format!("{}/", rooted) == prefix => PathBuf::new(),
+                None => return Resolved::Problem(Problem::OutsidePrefix),
+            }
+        },
+        None => page.parent().unwrap_or(Path::new("")).join(&path),
+    };
+    let mut normalized = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir if normalized.as_os_str().is_empty() => {
+                return Resolved::Problem(Problem::OutsideOutdir);
+            },
+            Component::ParentDir => { normalized.pop(); },
+            _ => {},
+        }
+    }
+    if path.ends_with('/') {
+        normalized.push("index.html");
+    }
+    Resolved::File(Some(normalized), fragment)
+}
+
+// the file a url is served from: as written, a directory's index.html,
+// or a bare url with .html added, as the dev server does
+fn find_file(files: &BTreeSet<PathBuf>, path: &Path) -> Option<PathBuf> {
+    let candidates = [
+        path.to_path_buf(),
+        path.join("index.html"),
+        path.with_extension("html"),
+    ];
+    candidates.into_iter().find(|candidate| files.contains(candidate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let dir = std::env::temp_dir().join("altwebgen-check");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("index.html", concat!(
+                r##"<a href="/site/a/">a</a> <a href="a/#notes">notes</a> <a href="b.html">b</a> "##,
+                r##"<img src="/site/media/x.mp3"> <a href="https://example.com/">web</a> <a href="#">top</a> "##,
+                r##"<a href="/elsewhere/">outside</a> <a href="a/#gone">gone</a> <a href="../up.html">up</a> "##,
+                r##"<a href="/site/a">bare</a> <a href="#here" id="here">here</a>"##,
+            )),
+            ("a/index.html", r#"<h2 id="notes">Notes</h2><a href="../">home</a><a href="/site">root</a>"#),
+            ("media/x.mp3", "mp3"),
+        ] {
+            let path = dir.join(".dist").join(rel_path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .prefix("/site/")
+            .build().unwrap();
+        let report = check(&config).unwrap();
+        assert_eq!(report.pages, 2);
+        assert_eq!(report.links, 13);
+        let dangling: Vec<(&str, Problem)> = report.dangling.iter()
+            .map(|link| (link.target.as_str(), link.problem.clone()))
+            .collect();
+        assert_eq!(dangling, vec![
+            ("b.html", Problem::Missing),
+            ("/elsewhere/", Problem::OutsidePrefix),
+            ("a/#gone", Problem::MissingAnchor(String::from("gone"))),
+            ("../up.html", Problem::OutsideOutdir),
+        ]);
+        assert!(report.to_string().ends_with("checked 13 links on 2 pages: 4 dangling"));
+    }
+}
@@ -0,0 +1,358 @@
+// builds a Config without touching the filesystem, except to read _site.yaml
+// directories are created separately, see ProjectLayout::ensure_dirs
+use anyhow::{bail, Context as _};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+use super::{read_site_yaml, root_prefix_format, Config, Feed, Mode, Robots, Taxonomy, Transcript, DEFAULT_ADDR};
+use crate::util::Attributes;
+
+#[derive(Clone, Debug)]
+pub struct ConfigBuilder {
+    outdir: PathBuf,
+    sourcedir: PathBuf,
+    templatedirs: Vec<PathBuf>,
+    refdir: Option<PathBuf>,
+    refroots: BTreeMap<String, PathBuf>,
+    builddir: Option<PathBuf>,
+    prefix: String,
+    permalink: Option<String>,
+    taxonomies: BTreeMap<String, Taxonomy>,
+    feeds: BTreeMap<String, Feed>,
+    robots: Robots,
+    mode: Mode,
+    transcript: Transcript,
+    addr: SocketAddr,
+    jobs: usize,
+    profile: Option<String>,
+    drafts: Option<bool>,
+    future: Option<bool>,
+    expired: Option<bool>,
+    cache: bool,
+    fail_fast: bool,
+    site: Attributes,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> ConfigBuilder {
+        ConfigBuilder {
+            outdir: PathBuf::from(".dist"),
+            sourcedir: PathBuf::from("source"),
+            templatedirs: vec![PathBuf::from("template")],
+            refdir: None,
+            refroots: BTreeMap::new(),
+            builddir: None,
+            prefix: String::new(),
+            permalink: None,
+            taxonomies: BTreeMap::new(),
+            feeds: BTreeMap::new(),
+            robots: Robots::default(),
+            mode: Mode::Build,
+            transcript: Transcript::Static,
+            addr: DEFAULT_ADDR,
+            jobs: 0,
+            profile: None,
+            drafts: None,
+            future: None,
+            expired: None,
+            cache: true,
+            fail_fast: false,
+            site: Attributes::new(),
+        }
+    }
+}
+
+impl ConfigBuilder {
+    pub fn outdir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.outdir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn sourcedir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.sourcedir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    // template layers, later ones shadow earlier ones
+    pub fn templatedirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.templatedirs = dirs;
+        self
+    }
+
+    // default: sibling of sourcedir
+    pub fn refdir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.refdir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // shared ref directory, its partials are named <namespace>/ref/<name>.html
+    pub fn refroot<P: AsRef<Path>>(mut self, namespace: &str, dir: P) -> Self {
+        self.refroots.insert(namespace.to_string(), dir.as_ref().to_path_buf());
+        self
+    }
+
+    // default: sibling of sourcedir
+    pub fn builddir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.builddir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    // default: none, each page keeps its source path, foo.md → foo.html
+    pub fn permalink(mut self, pattern: &str) -> Self {
+        self.permalink = Some(pattern.to_string());
+        self
+    }
+
+    // listing pages for the front matter key name, e.g. tags
+    pub fn taxonomy(mut self, name: &str, taxonomy: Taxonomy) -> Self {
+        self.taxonomies.insert(name.to_string(), taxonomy);
+        self
+    }
+
+    // <name>.xml and <name>.json, and <name>.rss for a podcast
+    pub fn feed(mut self, name: &str, feed: Feed) -> Self {
+        self.feeds.insert(name.to_string(), feed);
+        self
+    }
+
+    // default: robots.txt allows everything and points to sitemap.xml
+    pub fn robots(mut self, robots: Robots) -> Self {
+        self.robots = robots;
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn transcript(mut self, transcript: Transcript) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    pub fn addr(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    // worker threads for rendering, default 0: one per cpu
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    // default depends on mode, see Mode::default_profile
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    // default: only in dev mode
+    pub fn drafts(mut self, drafts: bool) -> Self {
+        self.drafts = Some(drafts);
+        self
+    }
+
+    // pages with a date after the build, default: only in dev mode
+    pub fn future(mut self, future: bool) -> Self {
+        self.future = Some(future);
+        self
+    }
+
+    // pages past their expires date, default: only in dev mode
+    pub fn expired(mut self, expired: bool) -> Self {
+        self.expired = Some(expired);
+        self
+    }
+
+    // default: true, false renders every page from scratch
+    pub fn cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    // default: false, every failure is reported before the build fails
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    // site attribute, replacing the one with the same key in _site.yaml
+    pub fn site(mut self, key: &str, value: Value) -> Self {
+        self.site.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Config> {
+        if self.templatedirs.is_empty() {
+            bail!("at least one template directory is required");
+        }
+        let site_attr = read_site_yaml(&self.sourcedir)
+            .with_context(|| format!("could not read _site.yaml from {}", self.sourcedir.display()))?;
+        let config = self.resolve(site_attr);
+        config.validate()?;
+        Ok(config)
+    }
+
+    // never fails, for Config::default and the deprecated Config::new:
+    // a _site.yaml that can't be read and settings that don't validate
+    // are logged, as Config::new always did
+    pub(super) fn build_lenient(mut self) -> Config {
+        if self.templatedirs.is_empty() {
+            self.templatedirs.push(PathBuf::from("template"));
+        }
+        let site_attr = read_site_yaml(&self.sourcedir).unwrap_or_else(|e| {
+            error!("could not read _site.yaml from {}, {:?}", self.sourcedir.display(), e);
+            Attributes::new()
+        });
+        let config = self.resolve(site_attr);
+        if let Err(e) = config.validate() {
+            error!("invalid config: {:#}", e);
+        }
+        config
+    }
+
+    fn resolve(self, mut site_attr: Attributes) -> Config {
+        site_attr.extend(self.site);
+
+        let refdir = self.refdir.unwrap_or_else(|| sourcedir_sibling(&self.sourcedir, "ref"));
+        let builddir = self.builddir.unwrap_or_else(|| sourcedir_sibling(&self.sourcedir, ".build"));
+        let config = Config {
+            outdir: self.outdir,
+            builddir,
+            sourcedir: self.sourcedir,
+            templatedirs: self.templatedirs,
+            refdir,
+            refroots: self.refroots,
+            site_attr,
+            prefix: root_prefix_format(&self.prefix),
+            permalink: self.permalink,
+            taxonomies: self.taxonomies,
+            feeds: self.feeds,
+            robots: self.robots,
+            mode: self.mode,
+            transcript: self.transcript,
+            addr: self.addr,
+            jobs: self.jobs,
+            profile: self.profile.unwrap_or_else(|| self.mode.default_profile().to_string()),
+            drafts: self.drafts.unwrap_or(self.mode == Mode::Dev),
+            future: self.future.unwrap_or(self.mode == Mode::Dev),
+            expired: self.expired.unwrap_or(self.mode == Mode::Dev),
+            cache: self.cache,
+            fail_fast: self.fail_fast,
+        };
+        info!("config...");
+        info!("   outdir:      {}", config.outdir.display());
+        info!("   sourcedir:   {}", config.sourcedir.display());
+        info!("   templatedir: {}", config.templatedir().display());
+        info!("   refdir:      {}", config.refdir.display());
+        for (namespace, dir) in &config.refroots {
+            info!("   refroot {}: {}", namespace, dir.display());
+        }
+        info!("   builddir:    {}", config.builddir.display());
+        info!("   prefix: {}", config.prefix);
+        if let Some(permalink) = &config.permalink {
+            info!("   permalink: {}", permalink);
+        }
+        for (name, taxonomy) in &config.taxonomies {
+            info!("   taxonomy {}: {}", name, taxonomy);
+        }
+        for (name, feed) in &config.feeds {
+            info!("   feed {}: {}", name, feed);
+        }
+        info!("   robots: {}", config.robots);
+        info!("   mode: {}", config.mode);
+        info!("   transcript: {}", config.transcript);
+        info!("   profile: {}", config.profile);
+        info!("   drafts: {}", config.drafts);
+        info!("   future: {}", config.future);
+        info!("   expired: {}", config.expired);
+        info!("   cache: {}", config.cache);
+        config
+    }
+}
+
+// a bare name like "source" has an empty parent: the sibling is relative too
+fn sourcedir_sibling(sourcedir: &Path, name: &str) -> PathBuf {
+    sourcedir.parent().unwrap_or(Path::new("")).join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_defaults() {
+        let config = Config::builder().build().unwrap();
+        assert_eq!(config.refdir, PathBuf::from("ref"));
+        assert_eq!(config.builddir, PathBuf::from(".build"));
+        assert_eq!(config.prefix, "/");
+        assert_eq!(config.profile, "production");
+        assert!(!config.drafts);
+        assert!(!config.future);
+        assert!(!config.expired);
+    }
+
+    #[test]
+    // no parent to put siblings in: an error rather than a panic,
+    // since every other directory would be inside sourcedir
+    fn test_builder_root_sourcedir() {
+        let config = Config::builder().sourcedir("/").outdir("/tmp/out").build();
+        assert!(config.is_err());
+    }
+
+    #[test]
+    // Config::default and Config::new log a bad _site.yaml instead of panicking
+    fn test_build_lenient() {
+        let dir = std::env::temp_dir().join("altwebgen-builder-lenient");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source")).unwrap();
+        std::fs::write(dir.join("source/_site.yaml"), "title: [unclosed").unwrap();
+        assert!(Config::builder().sourcedir(dir.join("source")).build().is_err());
+        let config = Config::builder().sourcedir(dir.join("source")).build_lenient();
+        assert!(config.site_attr.is_empty());
+    }
+
+    #[test]
+    fn test_builder_creates_nothing() {
+        let dir = std::env::temp_dir().join("altwebgen-builder-pure");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config::builder()
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .outdir(dir.join(".dist"))
+            .mode(Mode::Dev)
+            .site("title", Value::String("Built".into()))
+            .build().unwrap();
+        assert!(!dir.exists());
+        assert_eq!(config.builddir, dir.join(".build"));
+        assert_eq!(config.profile, "dev");
+        assert!(config.drafts);
+        assert!(config.future);
+        assert!(config.expired);
+        assert_eq!(config.site_attr["title"], Value::String("Built".into()));
+    }
+
+    #[test]
+    fn test_builder_bad_site_yaml() {
+        let dir = std::env::temp_dir().join("altwebgen-builder-site-yaml");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source")).unwrap();
+        std::fs::write(dir.join("source/_site.yaml"), "- a list").unwrap();
+        let result = Config::builder()
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .outdir(dir.join(".dist"))
+            .build();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,45 @@
+// the directories a project needs on disk
+// a Config only describes them, ensure_dirs creates any that are missing
+use anyhow::Context as _;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProjectLayout {
+    pub sourcedir: PathBuf,
+    pub refdir: PathBuf,
+    // the project's own template directory, other layers are shared and left alone
+    pub templatedir: PathBuf,
+    pub outdir: PathBuf,
+    pub builddir: PathBuf,
+}
+
+impl ProjectLayout {
+    pub fn ensure_dirs(&self) -> anyhow::Result<()> {
+        for dir in [&self.sourcedir, &self.refdir, &self.templatedir, &self.outdir, &self.builddir] {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create directory: {}", dir.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    #[test]
+    fn test_ensure_dirs() {
+        let dir = std::env::temp_dir().join("altwebgen-layout");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config::builder()
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("shared"), dir.join("template")])
+            .outdir(dir.join(".dist"))
+            .build().unwrap();
+        config.layout().ensure_dirs().unwrap();
+        for name in ["source", "ref", "template", ".dist", ".build"] {
+            assert!(dir.join(name).is_dir(), "{}", name);
+        }
+        assert!(!dir.join("shared").exists());
+    }
+}
@@ -1,18 +1,46 @@
-use anyhow::anyhow;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::path::{Path,PathBuf};
-use tracing::{error, info};
+
+use crate::util::{parse_yaml_attributes, Attributes};
 
 // export types for config fields
 mod mode;
 pub use mode::Mode as Mode;
 mod transcript;
 pub use transcript::Transcript as Transcript;
+pub mod project;
+pub use project::{Feed, Robots, Taxonomy};
+mod settings;
+pub use settings::{Origin, Setting, Settings};
+mod validate;
+mod builder;
+pub use builder::ConfigBuilder;
+mod layout;
+pub use layout::ProjectLayout;
+
+// default address for the dev server
+pub const DEFAULT_ADDR: SocketAddr = SocketAddr::new(
+    std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 3456);
 
 #[derive(Clone, Debug)]
 pub struct Context<'a> {
     pub config: &'a Config,
-    pub hbs: handlebars::Handlebars<'a>
+    // helpers own what they need, so templates outlive the config they came from
+    pub hbs: handlebars::Handlebars<'static>,
+    // every published page, templates see it as site.pages, see web::pages
+    pub pages: Vec<Attributes>,
+    // site attributes with the pages, shared by every render, see set_pages
+    pub site: crate::web::SiteData,
+    // where each source of the build is written
+    pub outpaths: crate::web::Outpaths,
+}
+
+impl Context<'_> {
+    pub fn set_pages(&mut self, pages: Vec<Attributes>) {
+        self.site = crate::web::SiteData::new(self.config, &pages);
+        self.pages = pages;
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -20,11 +48,38 @@ pub struct Config {
     pub outdir: PathBuf,
     pub builddir: PathBuf,
     pub sourcedir: PathBuf,
-    pub templatedir: PathBuf,
-    pub site_attr: HashMap<String, String>,
+    // template layers, later ones shadow earlier ones by relative path
+    pub templatedirs: Vec<PathBuf>,
+    pub refdir: PathBuf,
+    // shared ref directories by namespace, e.g. archive: {{> archive/ref/x.html}}
+    pub refroots: BTreeMap<String, PathBuf>,
+    pub site_attr: Attributes,
     pub prefix: String,
+    // site-wide permalink pattern, e.g. "/:section/:slug/", see web::permalink
+    pub permalink: Option<String>,
+    // listing pages for front matter keys like tags, see web::taxonomy
+    pub taxonomies: BTreeMap<String, Taxonomy>,
+    // Atom, JSON and podcast feeds by name, see web::feed
+    pub feeds: BTreeMap<String, Feed>,
+    // written with sitemap.xml when the site has a baseurl, see web::sitemap
+    pub robots: Robots,
     pub mode: Mode,
-    pub transcript: Transcript
+    pub transcript: Transcript,
+    pub addr: SocketAddr,
+    // worker threads for rendering, 0 for one per cpu
+    pub jobs: usize,
+    // build profile name, available to templates as `profile`
+    pub profile: String,
+    // render pages marked as drafts
+    pub drafts: bool,
+    // render pages with a date after the build
+    pub future: bool,
+    // render pages past their expires date
+    pub expired: bool,
+    // skip pages unchanged since the last build, see web::cache
+    pub cache: bool,
+    // stop at the first failure instead of reporting every one
+    pub fail_fast: bool
 }
 
 // ensure prefix starts and ends with '/'
@@ -52,32 +107,38 @@ impl Config {
     // used for when templates have associated media files or css
     // so they can be kept in the template directory
     // when building, they are automatically copied to the outdir
+    // from the build template directory (theme + templatedir)
     pub fn templatedir_outpath<P: AsRef<Path>>(&self, sourcepath: P) -> std::io::Result<PathBuf> {
         let rel_path = sourcepath.as_ref()
-            .strip_prefix(&self.templatedir)
-            .expect("templatedir strip prefix match");
+            .strip_prefix(self.buildtemplatedir())
+            .expect("buildtemplatedir strip prefix match");
         Ok(self.outdir.join(rel_path))
     }
 
+    // the project's own template directory: the last layer
+    pub fn templatedir(&self) -> &Path {
+        self.templatedirs.last().expect("at least one template directory")
+    }
+
     pub fn buildtemplatedir(&self) -> PathBuf {
          self.builddir.join("template")
     }
 
-    pub fn create_source_dirs(&self) -> anyhow::Result<()> {
-        std::fs::create_dir_all(&self.sourcedir).map_err(|e| {
-            anyhow!(format!("failed to create directory: {}, error: {}", self.sourcedir.display(), e))
-        })?;
-        let refdir = "ref";    // TODO: config?
-        std::fs::create_dir_all(refdir).map_err(|e| {
-            anyhow!(format!("failed to create directory: {}, error: {}", refdir, e))
-        })?;
-        std::fs::create_dir_all(&self.templatedir).map_err(|e| {
-            anyhow!(format!("failed to create directory: {}, error: {}", self.templatedir.display(), e))
-        })?;
-        Ok(())
+    // every ref directory with its namespace, the project's own refdir first with none
+    pub fn ref_roots(&self) -> Vec<(Option<&str>, &Path)> {
+        let mut roots: Vec<(Option<&str>, &Path)> = vec![(None, &self.refdir)];
+        roots.extend(self.refroots.iter().map(|(namespace, dir)| (Some(namespace.as_str()), dir.as_path())));
+        roots
     }
 
+    // start from the defaults: source, template, .dist, no prefix, build mode
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
 
+    // kept for library users, the binary uses the builder
+    #[allow(dead_code)]
+    #[deprecated(note = "use Config::builder(), which reports a bad _site.yaml or invalid settings as errors")]
     pub fn new(outdir_str: &str,
            sourcedir_str: &str,
            templatedir_str: &str,
@@ -85,91 +146,58 @@ impl Config {
            mode: Mode,
            transcript: Transcript
     ) -> Config {
-        info!("config...");
-        info!("   outdir:      {}", outdir_str);
-        info!("   sourcedir:   {}", sourcedir_str);
-        info!("   templatedir: {}", templatedir_str);
-        info!("   prefix: {}",      path_prefix);
-        info!("   mode: {}",      mode);
-        info!("   transcript: {}",   transcript);
-
-        // create output directory if not present
-        let outdir: PathBuf = std::path::PathBuf::from(outdir_str);
-        if !outdir.exists() {
-            let _result = std::fs::create_dir_all(&outdir);
-            if _result.is_err() {
-                error!("could not create default output director '.dist'");
-            }
-        }
-
-        // create source directory if not present
-        let sourcedir = PathBuf::from(sourcedir_str);
-        let site_attr = match read_site_yaml(&sourcedir) {
-            Ok(h) => h,
-            Err(e) => {
-                error!("could not read _site.yaml from {}, {:?}", &sourcedir.display(), e);
-                HashMap::new()
-            }
-        };
-
-        // create template directory if not present
-        let templatedir: PathBuf = std::path::PathBuf::from(templatedir_str);
-        if !templatedir.exists() {
-            let _result = std::fs::create_dir_all(&templatedir);
-            if _result.is_err() {
-                error!("could not create templatedir directory");
-            }
-        }
-
-
-        // build dir and sub-directories not configurable
-        info!("   -- additional directories not user configurable -- ");
-        info!("   -- builddir will be created as sibling of sourcedir -- ");
-        let build_str = ".build";
-        let builddir = sourcedir.parent().unwrap().join(build_str);
-        info!("   builddir: {}", builddir.display());
-        if !builddir.exists() {
-            let _result = std::fs::create_dir_all(&builddir);
-            if _result.is_err() {
-                error!("could not create default build director '.build'");
-            }
-        }
+        Config::builder()
+            .outdir(outdir_str)
+            .sourcedir(sourcedir_str)
+            .templatedirs(vec![PathBuf::from(templatedir_str)])
+            .prefix(path_prefix)
+            .mode(mode)
+            .transcript(transcript)
+            .build_lenient()
+    }
 
-        // create build template directory if not present
-        let buildtemplatedir = builddir.join("template");
-        info!("   buildtemplatedir: {}", buildtemplatedir.display());
-        if !buildtemplatedir.exists() {
-            if let Err(e) = std::fs::create_dir_all(&builddir) {
-                error!("could not create build template directory: {}, error: {}", buildtemplatedir.display(), e)
-            };
-        }
+    #[allow(dead_code)]
+    #[deprecated(note = "use config.layout().ensure_dirs()")]
+    pub fn create_source_dirs(&self) -> anyhow::Result<()> {
+        self.layout().ensure_dirs()
+    }
 
-        // ensure prefix starts and ends with '/'
-        let prefix = root_prefix_format(path_prefix);
-
-        Config {
-            outdir,
-            builddir,
-            sourcedir,
-            templatedir,
-            site_attr,
-            prefix: prefix.to_string(),
-            mode,
-            transcript
+    // directories this config reads from and writes to
+    pub fn layout(&self) -> ProjectLayout {
+        ProjectLayout {
+            sourcedir: self.sourcedir.clone(),
+            refdir: self.refdir.clone(),
+            templatedir: self.templatedir().to_path_buf(),
+            outdir: self.outdir.clone(),
+            builddir: self.builddir.clone(),
         }
+    }
+}
 
+// generated ref partials, relative to buildtemplatedir: ref or <namespace>/ref
+pub fn ref_partial_dir(namespace: Option<&str>) -> PathBuf {
+    match namespace {
+        None => PathBuf::from("ref"),
+        Some(namespace) => Path::new(namespace).join("ref"),
     }
+}
 
+// audio copied from refs, relative to outdir: media or media/<namespace>
+pub fn ref_media_dir(namespace: Option<&str>) -> String {
+    match namespace {
+        None => String::from("media"),
+        Some(namespace) => format!("media/{}", namespace),
+    }
 }
 
-fn read_site_yaml(sourcedir: &Path) -> anyhow::Result<HashMap<String, String>> {
+fn read_site_yaml(sourcedir: &Path) -> anyhow::Result<Attributes> {
 
     let site_yaml_path = sourcedir.join("_site.yaml");
-    let site_attr:HashMap<String, String>  = if !site_yaml_path.exists() {
-        HashMap::new()
+    let site_attr = if !site_yaml_path.exists() {
+        Attributes::new()
     } else {
-        let f = std::fs::File::open(&site_yaml_path)?;
-        serde_yaml::from_reader(f)?
+        let yaml_string = std::fs::read_to_string(&site_yaml_path)?;
+        parse_yaml_attributes(&yaml_string)?
     };
     Ok(site_attr)
 }
@@ -177,12 +205,7 @@ fn read_site_yaml(sourcedir: &Path) -> anyhow::Result<HashMap<String, String>> {
 impl Default for Config {
 
      fn default() -> Config {
-        info!("default config");
-        Config::new(".dist",
-            "source",
-            "template",
-            "",
-            Mode::Build, Transcript::Static)
+        Config::builder().build_lenient()
     }
 }
 
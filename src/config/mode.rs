@@ -1,11 +1,24 @@
+use clap::ValueEnum;
 use std::fmt;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
 pub enum Mode {
+    /// dev server with live reload
     Dev,
+    /// static files for deployment
     Build
 }
 
+impl Mode {
+    // build profile used when none is chosen
+    pub fn default_profile(&self) -> &'static str {
+        match self {
+            Mode::Dev => "dev",
+            Mode::Build => "production"
+        }
+    }
+}
+
 impl fmt::Display for Mode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = match self {
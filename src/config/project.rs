@@ -0,0 +1,271 @@
+use anyhow::Context as _;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use super::Transcript;
+use crate::util::Attributes;
+
+pub const PROJECT_FILE_NAME: &str = "altwebgen.toml";
+
+// project-level settings, read from altwebgen.toml
+// every field is optional: anything missing falls back to the built-in default
+// relative paths are relative to the directory that contains the file
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectFile {
+    pub sourcedir: Option<PathBuf>,
+    pub outdir: Option<PathBuf>,
+    pub templatedir: Option<TemplateDirs>,
+    pub refdir: Option<PathBuf>,
+    // shared ref directories by namespace
+    pub refroots: Option<BTreeMap<String, PathBuf>>,
+    pub builddir: Option<PathBuf>,
+    pub prefix: Option<String>,
+    // site-wide permalink pattern, e.g. "/:section/:slug/"
+    pub permalink: Option<String>,
+    pub transcript: Option<Transcript>,
+    pub addr: Option<SocketAddr>,
+    // worker threads for rendering, 0 for one per cpu
+    pub jobs: Option<usize>,
+    // listing pages by front matter key: [taxonomies.<name>]
+    pub taxonomies: Option<BTreeMap<String, Taxonomy>>,
+    // Atom and JSON feeds of the site's pages: [feeds.<name>]
+    pub feeds: Option<BTreeMap<String, Feed>>,
+    // rules for robots.txt, written with sitemap.xml: [robots]
+    pub robots: Option<Robots>,
+    // named build profiles: [profile.<name>]
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+// settings for one build profile, e.g. dev, staging or production
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub prefix: Option<String>,
+    // render draft pages
+    pub drafts: Option<bool>,
+    // render pages dated in the future
+    pub future: Option<bool>,
+    // render pages past their expires date
+    pub expired: Option<bool>,
+    // added to site attributes from _site.yaml, replacing any with the same key
+    pub site: Option<Attributes>,
+}
+
+// a front matter key whose values get a page each, e.g. tags or people
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Taxonomy {
+    // layout of each term's page, default: the default layout
+    pub layout: Option<String>,
+    // layout of the page listing every term
+    pub index_layout: Option<String>,
+}
+
+impl fmt::Display for Taxonomy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{ layout = \"{}\", index_layout = \"{}\" }}",
+            self.layout.as_deref().unwrap_or("default"), self.index_layout.as_deref().unwrap_or("default"))
+    }
+}
+
+// <name>.xml (Atom) and <name>.json (JSON Feed), needs the site attribute baseurl
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Feed {
+    // source directory whose pages are the entries, default: every page
+    pub section: Option<String>,
+    // newest entries, default 20
+    pub limit: Option<usize>,
+    // default: site title
+    pub title: Option<String>,
+    // also <name>.rss, with the audio of each page's refs as enclosures
+    #[serde(default)]
+    pub podcast: bool,
+}
+
+impl fmt::Display for Feed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{ section = \"{}\", limit = {}, podcast = {} }}",
+            self.section.as_deref().unwrap_or(""), self.limit.unwrap_or(20), self.podcast)
+    }
+}
+
+// robots.txt, pointing crawlers at sitemap.xml
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Robots {
+    // paths in the site that crawlers should skip, e.g. ["drafts/"]
+    #[serde(default)]
+    pub disallow: Vec<String>,
+    // added to the end as is, e.g. rules for one crawler
+    pub extra: Option<String>,
+}
+
+impl fmt::Display for Robots {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let disallow = self.disallow.iter()
+            .map(|path| format!("\"{}\"", path))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{{ disallow = [{}]{} }}", disallow, if self.extra.is_some() { ", extra = ..." } else { "" })
+    }
+}
+
+// templatedir is a single directory or a list of layers
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum TemplateDirs {
+    One(PathBuf),
+    Layers(Vec<PathBuf>),
+}
+
+impl TemplateDirs {
+    pub fn to_vec(&self) -> Vec<PathBuf> {
+        match self {
+            TemplateDirs::One(dir) => vec![dir.clone()],
+            TemplateDirs::Layers(dirs) => dirs.clone(),
+        }
+    }
+}
+
+impl ProjectFile {
+    // walk up from start directory, return path of the first project file found
+    pub fn find<P: AsRef<Path>>(start: P) -> Option<PathBuf> {
+        start.as_ref()
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_FILE_NAME))
+            .find(|candidate| candidate.is_file())
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<ProjectFile> {
+        let path = path.as_ref();
+        info!("reading project file: {}", path.display());
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        ProjectFile::parse(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<ProjectFile> {
+        Ok(toml::from_str(text)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        let project = ProjectFile::parse("").unwrap();
+        assert_eq!(project, ProjectFile::default());
+    }
+
+    #[test]
+    fn test_parse_all_fields() {
+        let project = ProjectFile::parse(r#"
+            sourcedir = "site/source"
+            outdir = "public"
+            templatedir = "site/template"
+            refdir = "audio"
+            builddir = "tmp/build"
+            prefix = "/blog/"
+            permalink = "/:section/:slug/"
+            transcript = "off"
+            addr = "0.0.0.0:8080"
+        "#).unwrap();
+        assert_eq!(project.sourcedir, Some(PathBuf::from("site/source")));
+        assert_eq!(project.refdir, Some(PathBuf::from("audio")));
+        assert_eq!(project.prefix.as_deref(), Some("/blog/"));
+        assert_eq!(project.permalink.as_deref(), Some("/:section/:slug/"));
+        assert_eq!(project.transcript, Some(Transcript::Off));
+        assert_eq!(project.addr, Some(SocketAddr::from(([0, 0, 0, 0], 8080))));
+    }
+
+    #[test]
+    fn test_parse_template_layers() {
+        let project = ProjectFile::parse(r#"templatedir = ["../theme", "template"]"#).unwrap();
+        let layers = project.templatedir.unwrap().to_vec();
+        assert_eq!(layers, vec![PathBuf::from("../theme"), PathBuf::from("template")]);
+    }
+
+    #[test]
+    fn test_parse_refroots() {
+        let project = ProjectFile::parse(r#"
+            [refroots]
+            archive = "../audio-archive"
+        "#).unwrap();
+        let refroots = project.refroots.unwrap();
+        assert_eq!(refroots["archive"], PathBuf::from("../audio-archive"));
+    }
+
+    #[test]
+    fn test_parse_taxonomies() {
+        let project = ProjectFile::parse(r#"
+            [taxonomies.tags]
+            [taxonomies.people]
+            layout = "person"
+        "#).unwrap();
+        let taxonomies = project.taxonomies.unwrap();
+        assert_eq!(taxonomies["tags"], Taxonomy::default());
+        assert_eq!(taxonomies["people"].layout.as_deref(), Some("person"));
+        assert!(ProjectFile::parse("[taxonomies.tags]\nlayuot = \"tag\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_feeds() {
+        let project = ProjectFile::parse(r#"
+            [feeds.quotes]
+            section = "quotes"
+            podcast = true
+        "#).unwrap();
+        let feed = &project.feeds.unwrap()["quotes"];
+        assert_eq!(feed.section.as_deref(), Some("quotes"));
+        assert!(feed.podcast);
+        assert_eq!(feed.limit, None);
+    }
+
+    #[test]
+    fn test_parse_robots() {
+        let project = ProjectFile::parse(r#"
+            [robots]
+            disallow = ["drafts/", "private/"]
+        "#).unwrap();
+        let robots = project.robots.unwrap();
+        assert_eq!(robots.disallow, vec!["drafts/", "private/"]);
+        assert_eq!(robots.extra, None);
+        assert!(ProjectFile::parse("[robots]\nallow = [\"a/\"]").is_err());
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let project = ProjectFile::parse(r#"
+            prefix = "/"
+
+            [profile.staging]
+            prefix = "/staging/"
+            drafts = true
+
+            [profile.production.site]
+            baseurl = "https://example.com"
+            analytics = { id = "UA-1234" }
+        "#).unwrap();
+        let staging = &project.profile["staging"];
+        assert_eq!(staging.prefix.as_deref(), Some("/staging/"));
+        assert_eq!(staging.drafts, Some(true));
+        let site = project.profile["production"].site.as_ref().unwrap();
+        assert_eq!(site["baseurl"], serde_json::json!("https://example.com"));
+        assert_eq!(site["analytics"]["id"], serde_json::json!("UA-1234"));
+    }
+
+    #[test]
+    fn test_parse_unknown_key() {
+        assert!(ProjectFile::parse("sorcedir = \"src\"").is_err());
+    }
+}
@@ -0,0 +1,609 @@
+use anyhow::bail;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use super::{
+    project::{Profile, ProjectFile},
+    Config, ConfigBuilder, Feed, Mode, Robots, Taxonomy, Transcript,
+    DEFAULT_ADDR,
+};
+
+const PROFILE_ENV: &str = "ALTWEBGEN_PROFILE";
+// ALTWEBGEN_SITE_<key>=<value> sets site attribute <key>
+const SITE_ENV_PREFIX: &str = "ALTWEBGEN_SITE_";
+
+// profiles that may be chosen without being defined in altwebgen.toml
+const BUILTIN_PROFILES: [&str; 3] = ["dev", "staging", "production"];
+
+// where a setting value came from, reported by `config show`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Origin {
+    Default,
+    ProjectFile(PathBuf),
+    Profile(String, PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::ProjectFile(path) => write!(f, "{}", path.display()),
+            Origin::Profile(name, path) => write!(f, "[profile.{}] in {}", name, path.display()),
+            Origin::Env(var) => write!(f, "env {}", var),
+            Origin::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+impl<T> Setting<T> {
+    pub fn new(value: T, origin: Origin) -> Self {
+        Setting { value, origin }
+    }
+
+    fn default(value: T) -> Self {
+        Setting::new(value, Origin::Default)
+    }
+
+    pub fn set(&mut self, value: T, origin: Origin) {
+        self.value = value;
+        self.origin = origin;
+    }
+
+    // profiles don't replace values given in the environment or on the command line
+    fn profile_can_override(&self) -> bool {
+        !matches!(self.origin, Origin::Env(_) | Origin::Cli)
+    }
+}
+
+// settings merged from built-in defaults, altwebgen.toml and CLI flags
+// (in that order, later ones win)
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub sourcedir: Setting<PathBuf>,
+    pub outdir: Setting<PathBuf>,
+    // template layers, later ones shadow earlier ones
+    pub templatedirs: Setting<Vec<PathBuf>>,
+    // refdir and builddir default to siblings of sourcedir
+    pub refdir: Option<Setting<PathBuf>>,
+    // shared ref directories by namespace
+    pub refroots: BTreeMap<String, Setting<PathBuf>>,
+    pub builddir: Option<Setting<PathBuf>>,
+    pub prefix: Setting<String>,
+    // site-wide permalink pattern, if not set: pages keep their source path
+    pub permalink: Option<Setting<String>>,
+    // listing pages by front matter key
+    pub taxonomies: BTreeMap<String, Setting<Taxonomy>>,
+    // feeds by name
+    pub feeds: BTreeMap<String, Setting<Feed>>,
+    // robots.txt rules, if not set: allow everything
+    pub robots: Option<Setting<Robots>>,
+    pub transcript: Setting<Transcript>,
+    pub addr: Setting<SocketAddr>,
+    pub jobs: Setting<usize>,
+    // chosen with --profile or ALTWEBGEN_PROFILE, otherwise depends on mode
+    pub profile: Option<Setting<String>>,
+    // profiles defined in altwebgen.toml
+    pub profiles: BTreeMap<String, Profile>,
+    profiles_path: PathBuf,
+    // render drafts, if not set: only in dev mode
+    pub drafts: Option<Setting<bool>>,
+    // render pages dated in the future, if not set: only in dev mode
+    pub future: Option<Setting<bool>>,
+    // render pages past their expires date, if not set: only in dev mode
+    pub expired: Option<Setting<bool>>,
+    // site attributes, replacing any with the same key in _site.yaml
+    pub site: BTreeMap<String, Setting<Value>>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::with_root(Path::new(""))
+    }
+}
+
+impl Settings {
+    // defaults for a project rooted at the given directory
+    pub fn with_root(root: &Path) -> Settings {
+        Settings {
+            sourcedir: Setting::default(root.join("source")),
+            outdir: Setting::default(root.join(".dist")),
+            templatedirs: Setting::default(vec![root.join("template")]),
+            refdir: None,
+            refroots: BTreeMap::new(),
+            builddir: None,
+            prefix: Setting::default(String::from("")),
+            permalink: None,
+            taxonomies: BTreeMap::new(),
+            feeds: BTreeMap::new(),
+            robots: None,
+            transcript: Setting::default(Transcript::Static),
+            addr: Setting::default(DEFAULT_ADDR),
+            jobs: Setting::default(0),
+            profile: None,
+            profiles: BTreeMap::new(),
+            profiles_path: PathBuf::new(),
+            drafts: None,
+            future: None,
+            expired: None,
+            site: BTreeMap::new(),
+        }
+    }
+
+    // defaults, overridden by altwebgen.toml if one is found
+    // in the working directory or any of its parents,
+    // then by environment variables
+    pub fn load() -> anyhow::Result<Settings> {
+        let mut settings = Settings::load_project_file()?;
+        settings.apply_env(std::env::vars_os());
+        Ok(settings)
+    }
+
+    fn load_project_file() -> anyhow::Result<Settings> {
+        let cwd = std::env::current_dir()?;
+        match ProjectFile::find(&cwd) {
+            None => Ok(Settings::default()),
+            Some(path) => {
+                // keep paths relative when the project file is in the working directory
+                let path = match path.strip_prefix(&cwd) {
+                    Ok(rel_path) => rel_path.to_path_buf(),
+                    Err(_) => path
+                };
+                let project = ProjectFile::read(&path)?;
+                let mut settings = Settings::with_root(path.parent().unwrap_or(Path::new("")));
+                settings.apply_project_file(&project, &path);
+                Ok(settings)
+            }
+        }
+    }
+
+    pub fn apply_project_file(&mut self, project: &ProjectFile, path: &Path) {
+        let origin = Origin::ProjectFile(path.to_path_buf());
+        let root = path.parent().unwrap_or(Path::new(""));
+        if let Some(dir) = &project.sourcedir {
+            self.sourcedir.set(root.join(dir), origin.clone());
+        }
+        if let Some(dir) = &project.outdir {
+            self.outdir.set(root.join(dir), origin.clone());
+        }
+        if let Some(dirs) = &project.templatedir {
+            let dirs = dirs.to_vec().iter().map(|dir| root.join(dir)).collect();
+            self.templatedirs.set(dirs, origin.clone());
+        }
+        if let Some(dir) = &project.refdir {
+            self.refdir = Some(Setting::new(root.join(dir), origin.clone()));
+        }
+        for (namespace, dir) in project.refroots.iter().flatten() {
+            self.refroots.insert(namespace.clone(), Setting::new(root.join(dir), origin.clone()));
+        }
+        if let Some(dir) = &project.builddir {
+            self.builddir = Some(Setting::new(root.join(dir), origin.clone()));
+        }
+        if let Some(prefix) = &project.prefix {
+            self.prefix.set(prefix.clone(), origin.clone());
+        }
+        if let Some(permalink) = &project.permalink {
+            self.permalink = Some(Setting::new(permalink.clone(), origin.clone()));
+        }
+        for (name, taxonomy) in project.taxonomies.iter().flatten() {
+            self.taxonomies.insert(name.clone(), Setting::new(taxonomy.clone(), origin.clone()));
+        }
+        for (name, feed) in project.feeds.iter().flatten() {
+            self.feeds.insert(name.clone(), Setting::new(feed.clone(), origin.clone()));
+        }
+        if let Some(robots) = &project.robots {
+            self.robots = Some(Setting::new(robots.clone(), origin.clone()));
+        }
+        if let Some(transcript) = project.transcript {
+            self.transcript.set(transcript, origin.clone());
+        }
+        if let Some(addr) = project.addr {
+            self.addr.set(addr, origin.clone());
+        }
+        if let Some(jobs) = project.jobs {
+            self.jobs.set(jobs, origin);
+        }
+        self.profiles = project.profile.clone();
+        self.profiles_path = path.to_path_buf();
+    }
+
+    // environment variables as given, e.g. std::env::vars_os()
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (OsString, OsString)>) {
+        for (var, value) in vars {
+            let (Some(var), Some(value)) = (var.to_str(), value.to_str()) else {
+                continue;
+            };
+            if var == PROFILE_ENV {
+                self.profile = Some(Setting::new(value.to_string(), Origin::Env(PROFILE_ENV.into())));
+            } else if let Some(key) = var.strip_prefix(SITE_ENV_PREFIX) {
+                if !key.is_empty() {
+                    self.site.insert(key.to_string(),
+                        Setting::new(site_value(value), Origin::Env(var.to_string())));
+                }
+            }
+        }
+    }
+
+    // shared ref directory from `namespace=dir`, as given to --refroot
+    pub fn set_refroot(&mut self, assignment: &str, origin: Origin) -> anyhow::Result<()> {
+        let Some((namespace, dir)) = assignment.split_once('=') else {
+            bail!("expected namespace=dir, found: {}", assignment);
+        };
+        self.refroots.insert(namespace.trim().to_string(), Setting::new(PathBuf::from(dir), origin));
+        Ok(())
+    }
+
+    // site attribute from `key=value`, as given to --set
+    pub fn set_site(&mut self, assignment: &str, origin: Origin) -> anyhow::Result<()> {
+        let Some((key, value)) = assignment.split_once('=') else {
+            bail!("expected key=value, found: {}", assignment);
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            bail!("missing site attribute name in: {}", assignment);
+        }
+        self.site.insert(key.to_string(), Setting::new(site_value(value), origin));
+        Ok(())
+    }
+
+    // settings with the active profile applied:
+    // the chosen one, or the default for mode
+    pub fn with_profile(&self, mode: Mode) -> anyhow::Result<Settings> {
+        let mut settings = self.clone();
+        let name = self.profile.clone()
+            .unwrap_or_else(|| Setting::default(mode.default_profile().to_string()));
+        match self.profiles.get(&name.value) {
+            None => {
+                if !BUILTIN_PROFILES.contains(&name.value.as_str()) {
+                    bail!("unknown profile '{}' ({}), expected one of: {}", name.value, name.origin,
+                        self.profile_names().join(", "));
+                }
+            },
+            Some(profile) => {
+                let origin = Origin::Profile(name.value.clone(), self.profiles_path.clone());
+                if let Some(prefix) = &profile.prefix {
+                    if settings.prefix.profile_can_override() {
+                        settings.prefix.set(prefix.clone(), origin.clone());
+                    }
+                }
+                if let Some(drafts) = profile.drafts {
+                    if settings.drafts.iter().all(|d| d.profile_can_override()) {
+                        settings.drafts = Some(Setting::new(drafts, origin.clone()));
+                    }
+                }
+                if let Some(future) = profile.future {
+                    if settings.future.iter().all(|f| f.profile_can_override()) {
+                        settings.future = Some(Setting::new(future, origin.clone()));
+                    }
+                }
+                if let Some(expired) = profile.expired {
+                    if settings.expired.iter().all(|e| e.profile_can_override()) {
+                        settings.expired = Some(Setting::new(expired, origin.clone()));
+                    }
+                }
+                for (key, value) in profile.site.iter().flatten() {
+                    if settings.site.get(key).into_iter().all(|s| s.profile_can_override()) {
+                        settings.site.insert(key.clone(), Setting::new(value.clone(), origin.clone()));
+                    }
+                }
+            }
+        }
+        settings.profile = Some(name);
+        Ok(settings)
+    }
+
+    fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILTIN_PROFILES.iter().map(|name| name.to_string()).collect();
+        for name in self.profiles.keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    fn sourcedir_sibling(&self, name: &str) -> Setting<PathBuf> {
+        let parent = self.sourcedir.value.parent().unwrap_or(Path::new(""));
+        Setting::default(parent.join(name))
+    }
+
+    // the project's own template directory: the last layer
+    pub fn templatedir(&self) -> PathBuf {
+        self.templatedirs.value.last().cloned().unwrap_or_default()
+    }
+
+    pub fn refdir(&self) -> Setting<PathBuf> {
+        self.refdir.clone().unwrap_or_else(|| self.sourcedir_sibling("ref"))
+    }
+
+    pub fn builddir(&self) -> Setting<PathBuf> {
+        self.builddir.clone().unwrap_or_else(|| self.sourcedir_sibling(".build"))
+    }
+
+    pub fn config(&self, mode: Mode) -> anyhow::Result<Config> {
+        self.builder(mode)?.build()
+    }
+
+    // for settings that only exist as command flags, e.g. --no-cache
+    pub fn builder(&self, mode: Mode) -> anyhow::Result<ConfigBuilder> {
+        let settings = self.with_profile(mode)?;
+        let mut builder = Config::builder()
+            .outdir(&settings.outdir.value)
+            .sourcedir(&settings.sourcedir.value)
+            .templatedirs(settings.templatedirs.value.clone())
+            .refdir(settings.refdir().value)
+            .builddir(settings.builddir().value)
+            .prefix(&settings.prefix.value)
+            .mode(mode)
+            .transcript(settings.transcript.value)
+            .addr(settings.addr.value)
+            .jobs(settings.jobs.value);
+        if let Some(profile) = &settings.profile {
+            builder = builder.profile(&profile.value);
+        }
+        if let Some(permalink) = &settings.permalink {
+            builder = builder.permalink(&permalink.value);
+        }
+        if let Some(drafts) = &settings.drafts {
+            builder = builder.drafts(drafts.value);
+        }
+        if let Some(future) = &settings.future {
+            builder = builder.future(future.value);
+        }
+        if let Some(expired) = &settings.expired {
+            builder = builder.expired(expired.value);
+        }
+        for (namespace, setting) in &settings.refroots {
+            builder = builder.refroot(namespace, &setting.value);
+        }
+        for (name, setting) in &settings.taxonomies {
+            builder = builder.taxonomy(name, setting.value.clone());
+        }
+        for (name, setting) in &settings.feeds {
+            builder = builder.feed(name, setting.value.clone());
+        }
+        if let Some(robots) = &settings.robots {
+            builder = builder.robots(robots.value.clone());
+        }
+        for (key, setting) in &settings.site {
+            builder = builder.site(key, setting.value.clone());
+        }
+        Ok(builder)
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn line<T: fmt::Display>(f: &mut fmt::Formatter, key: &str, setting: &Setting<T>) -> fmt::Result {
+            let value = format!("\"{}\"", setting.value);
+            writeln!(f, "{:<11} = {:<24} # {}", key, value, setting.origin)
+        }
+        fn path_line(f: &mut fmt::Formatter, key: &str, setting: &Setting<PathBuf>) -> fmt::Result {
+            line(f, key, &Setting::new(setting.value.display(), setting.origin.clone()))
+        }
+        let transcript = match self.transcript.value {
+            Transcript::Off => "off",
+            Transcript::On => "on",
+            Transcript::Static => "static",
+        };
+
+        path_line(f, "sourcedir", &self.sourcedir)?;
+        path_line(f, "outdir", &self.outdir)?;
+        let templatedirs = self.templatedirs.value.iter()
+            .map(|dir| format!("\"{}\"", dir.display()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        writeln!(f, "{:<11} = {:<24} # {}", "templatedir", format!("[{}]", templatedirs), self.templatedirs.origin)?;
+        path_line(f, "refdir", &self.refdir())?;
+        for (namespace, setting) in &self.refroots {
+            path_line(f, &format!("refroots.{}", namespace), setting)?;
+        }
+        path_line(f, "builddir", &self.builddir())?;
+        line(f, "prefix", &self.prefix)?;
+        if let Some(permalink) = &self.permalink {
+            line(f, "permalink", permalink)?;
+        }
+        for (name, setting) in &self.taxonomies {
+            writeln!(f, "{:<11} = {:<24} # {}", format!("taxonomies.{}", name), setting.value.to_string(), setting.origin)?;
+        }
+        for (name, setting) in &self.feeds {
+            writeln!(f, "{:<11} = {:<24} # {}", format!("feeds.{}", name), setting.value.to_string(), setting.origin)?;
+        }
+        if let Some(robots) = &self.robots {
+            writeln!(f, "{:<11} = {:<24} # {}", "robots", robots.value.to_string(), robots.origin)?;
+        }
+        line(f, "transcript", &Setting::new(transcript, self.transcript.origin.clone()))?;
+        line(f, "addr", &self.addr)?;
+        writeln!(f, "{:<11} = {:<24} # {}", "jobs", self.jobs.value, self.jobs.origin)?;
+        if let Some(profile) = &self.profile {
+            line(f, "profile", profile)?;
+        }
+        if let Some(drafts) = &self.drafts {
+            writeln!(f, "{:<11} = {:<24} # {}", "drafts", drafts.value, drafts.origin)?;
+        }
+        if let Some(future) = &self.future {
+            writeln!(f, "{:<11} = {:<24} # {}", "future", future.value, future.origin)?;
+        }
+        if let Some(expired) = &self.expired {
+            writeln!(f, "{:<11} = {:<24} # {}", "expired", expired.value, expired.origin)?;
+        }
+        for (key, setting) in &self.site {
+            writeln!(f, "{:<11} = {:<24} # {}", format!("site.{}", key), setting.value.to_string(), setting.origin)?;
+        }
+        Ok(())
+    }
+}
+
+// booleans and numbers keep their type, anything else is a string
+// so values like urls or "Notes: part 1" are taken as written
+fn site_value(text: &str) -> Value {
+    match serde_yaml::from_str::<Value>(text) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_))) => value,
+        _ => Value::String(text.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let settings = Settings::default();
+        assert_eq!(settings.refdir(), Setting::default(PathBuf::from("ref")));
+        assert_eq!(settings.builddir(), Setting::default(PathBuf::from(".build")));
+    }
+
+    #[test]
+    fn test_project_file_relative_to_its_directory() {
+        let project = ProjectFile::parse(r#"
+            sourcedir = "site/source"
+            prefix = "blog"
+        "#).unwrap();
+        let path = PathBuf::from("../altwebgen.toml");
+        let mut settings = Settings::default();
+        settings.apply_project_file(&project, &path);
+
+        let origin = Origin::ProjectFile(path.clone());
+        assert_eq!(settings.sourcedir, Setting::new(PathBuf::from("../site/source"), origin.clone()));
+        assert_eq!(settings.prefix, Setting::new(String::from("blog"), origin));
+        assert_eq!(settings.outdir, Setting::default(PathBuf::from(".dist")));
+        // derived from the sourcedir set in the project file
+        assert_eq!(settings.refdir().value, PathBuf::from("../site/ref"));
+        assert_eq!(settings.refdir().origin, Origin::Default);
+    }
+
+    fn settings_with_profiles() -> Settings {
+        let project = ProjectFile::parse(r#"
+            prefix = "/"
+
+            [profile.staging]
+            prefix = "/staging/"
+            drafts = true
+            site = { baseurl = "https://staging.example.com" }
+
+            [profile.preview]
+            drafts = true
+        "#).unwrap();
+        let mut settings = Settings::default();
+        settings.apply_project_file(&project, Path::new("altwebgen.toml"));
+        settings
+    }
+
+    #[test]
+    fn test_profile_default_for_mode() {
+        let settings = settings_with_profiles().with_profile(Mode::Build).unwrap();
+        assert_eq!(settings.profile.unwrap().value, "production");
+        assert_eq!(settings.prefix.value, "/");
+        assert!(settings.drafts.is_none());
+    }
+
+    #[test]
+    fn test_profile_chosen() {
+        let mut settings = settings_with_profiles();
+        settings.profile = Some(Setting::new(String::from("staging"), Origin::Cli));
+        let config = settings.config(Mode::Build).unwrap();
+        assert_eq!(config.profile, "staging");
+        assert_eq!(config.prefix, "/staging/");
+        assert!(config.drafts);
+        assert_eq!(config.site_attr["baseurl"], Value::String("https://staging.example.com".into()));
+    }
+
+    #[test]
+    fn test_profile_does_not_override_cli() {
+        let mut settings = settings_with_profiles();
+        settings.profile = Some(Setting::new(String::from("staging"), Origin::Cli));
+        settings.prefix.set(String::from("/mine/"), Origin::Cli);
+        let settings = settings.with_profile(Mode::Build).unwrap();
+        assert_eq!(settings.prefix.value, "/mine/");
+    }
+
+    #[test]
+    fn test_profile_unknown() {
+        let mut settings = settings_with_profiles();
+        settings.profile = Some(Setting::new(String::from("prod"), Origin::Cli));
+        assert!(settings.with_profile(Mode::Build).is_err());
+    }
+
+    #[test]
+    fn test_refroots() {
+        let project = ProjectFile::parse(r#"refroots = { archive = "../archive" }"#).unwrap();
+        let mut settings = Settings::default();
+        settings.apply_project_file(&project, Path::new("site/altwebgen.toml"));
+        settings.set_refroot("quotes=/srv/quotes", Origin::Cli).unwrap();
+        assert!(settings.set_refroot("quotes", Origin::Cli).is_err());
+        let config = settings.config(Mode::Build).unwrap();
+        assert_eq!(config.refroots["archive"], PathBuf::from("site/../archive"));
+        assert_eq!(config.ref_roots(), vec![
+            (None, Path::new("ref")),
+            (Some("archive"), Path::new("site/../archive")),
+            (Some("quotes"), Path::new("/srv/quotes")),
+        ]);
+    }
+
+    #[test]
+    fn test_site_value() {
+        assert_eq!(site_value("true"), Value::Bool(true));
+        assert_eq!(site_value("42"), serde_json::json!(42));
+        assert_eq!(site_value("https://example.com"), Value::String("https://example.com".into()));
+        assert_eq!(site_value("Notes: part 1"), Value::String("Notes: part 1".into()));
+        assert_eq!(site_value(""), Value::String("".into()));
+    }
+
+    #[test]
+    fn test_set_site() {
+        let mut settings = Settings::default();
+        settings.set_site("baseurl=https://example.com/?a=b", Origin::Cli).unwrap();
+        assert_eq!(settings.site["baseurl"].value, Value::String("https://example.com/?a=b".into()));
+        assert!(settings.set_site("baseurl", Origin::Cli).is_err());
+        assert!(settings.set_site("=x", Origin::Cli).is_err());
+    }
+
+    #[test]
+    fn test_site_env() {
+        let vars = [
+            ("ALTWEBGEN_SITE_test_env_title", "From Env"),
+            ("ALTWEBGEN_SITE_", "no name"),
+            (PROFILE_ENV, "staging"),
+            ("HOME", "/root"),
+        ];
+        let mut settings = Settings::default();
+        settings.apply_env(vars.map(|(var, value)| (OsString::from(var), OsString::from(value))));
+        assert_eq!(settings.site.len(), 1);
+        let setting = &settings.site["test_env_title"];
+        assert_eq!(setting.value, Value::String("From Env".into()));
+        assert_eq!(setting.origin, Origin::Env("ALTWEBGEN_SITE_test_env_title".into()));
+        assert_eq!(settings.profile.unwrap().value, "staging");
+    }
+
+    #[test]
+    fn test_site_overrides_profile() {
+        let mut settings = settings_with_profiles();
+        settings.profile = Some(Setting::new(String::from("staging"), Origin::Cli));
+        settings.set_site("baseurl=https://ci.example.com", Origin::Cli).unwrap();
+        settings.set_site("analytics=UA-1", Origin::Cli).unwrap();
+        let config = settings.config(Mode::Build).unwrap();
+        assert_eq!(config.site_attr["baseurl"], Value::String("https://ci.example.com".into()));
+        assert_eq!(config.site_attr["analytics"], Value::String("UA-1".into()));
+    }
+
+    #[test]
+    fn test_cli_overrides_project_file() {
+        let project = ProjectFile::parse("outdir = \"public\"").unwrap();
+        let mut settings = Settings::default();
+        settings.apply_project_file(&project, Path::new("altwebgen.toml"));
+        settings.outdir.set(PathBuf::from("out"), Origin::Cli);
+        assert_eq!(settings.outdir, Setting::new(PathBuf::from("out"), Origin::Cli));
+    }
+}
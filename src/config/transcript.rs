@@ -1,9 +1,15 @@
+use clap::ValueEnum;
+use serde::Deserialize;
 use std::fmt;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum Transcript {
+    /// no transcript annotation
     Off = 0,
+    /// annotate, generating missing transcripts with whisperx
     On,
+    /// annotate only with transcripts already in the ref directory
     Static
 }
 
@@ -0,0 +1,185 @@
+// checks that a config is consistent before anything is created or cleaned:
+// outdir and builddir are deleted on every build, so they must not hold
+// (or be held by) any directory with the project's own files
+use anyhow::bail;
+use std::path::{Path, PathBuf};
+
+use super::Config;
+
+impl Config {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        check_prefix(&self.prefix)?;
+        if let Some(pattern) = &self.permalink {
+            crate::web::permalink::check_pattern(pattern)?;
+        }
+        for name in self.taxonomies.keys() {
+            check_output_name("taxonomy", name)?;
+        }
+        for name in self.feeds.keys() {
+            check_output_name("feed", name)?;
+            // sitemap.xml is taken
+            if name == "sitemap" {
+                bail!("invalid feed 'sitemap': its Atom feed would replace sitemap.xml");
+            }
+        }
+
+        let mut inputs: Vec<(&str, &Path)> = vec![
+            ("sourcedir", &self.sourcedir),
+            ("refdir", &self.refdir),
+        ];
+        for dir in &self.templatedirs {
+            inputs.push(("templatedir", dir));
+        }
+        for (namespace, dir) in &self.refroots {
+            check_namespace(namespace)?;
+            inputs.push(("refroot", dir));
+        }
+        for (name, dir) in &inputs {
+            check_dir(name, dir)?;
+        }
+        for dir in &self.templatedirs {
+            if same_path(dir, &self.sourcedir) {
+                bail!("templatedir and sourcedir are the same directory: {}", dir.display());
+            }
+        }
+
+        // generated directories, removed and recreated by each build
+        for (name, generated) in [("outdir", &self.outdir), ("builddir", &self.builddir)] {
+            check_dir(name, generated)?;
+            for (input_name, input) in &inputs {
+                if overlaps(generated, input) {
+                    bail!("{} {} overlaps {} {}, it would be deleted on build",
+                        name, generated.display(), input_name, input.display());
+                }
+            }
+        }
+        if overlaps(&self.outdir, &self.builddir) {
+            bail!("outdir {} overlaps builddir {}", self.outdir.display(), self.builddir.display());
+        }
+        Ok(())
+    }
+}
+
+// prefix is used as-is in urls
+pub fn check_prefix(prefix: &str) -> anyhow::Result<()> {
+    if let Some(c) = prefix.chars().find(|c| c.is_whitespace() || matches!(c, '\\' | '?' | '#')) {
+        bail!("prefix '{}' contains invalid character {:?}", prefix, c);
+    }
+    if prefix.contains("://") {
+        bail!("prefix '{}' should be a path, not a url (set site baseurl for the host)", prefix);
+    }
+    if prefix.split('/').any(|segment| segment == "..") {
+        bail!("prefix '{}' contains '..'", prefix);
+    }
+    Ok(())
+}
+
+// namespaces become directory names for partials and media
+pub fn check_namespace(namespace: &str) -> anyhow::Result<()> {
+    if namespace.is_empty() || namespace == "ref"
+        || !namespace.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("invalid ref namespace '{}': use letters, digits, '-' or '_', and not 'ref'", namespace);
+    }
+    Ok(())
+}
+
+// taxonomy and feed names are file and directory names in outdir
+pub fn check_output_name(kind: &str, name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("invalid {} '{}': use letters, digits, '-' or '_'", kind, name);
+    }
+    Ok(())
+}
+
+fn check_dir(name: &str, dir: &Path) -> anyhow::Result<()> {
+    if dir.as_os_str().is_empty() {
+        bail!("{} is empty", name);
+    }
+    if dir.exists() && !dir.is_dir() {
+        bail!("{} is not a directory: {}", name, dir.display());
+    }
+    Ok(())
+}
+
+// without touching the filesystem: directories may not exist yet
+fn normalize(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path).components().collect(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    normalize(a) == normalize(b)
+}
+
+// either directory contains the other
+fn overlaps(a: &Path, b: &Path) -> bool {
+    let (a, b) = (normalize(a), normalize(b));
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(outdir: &str, sourcedir: &str, templatedir: &str) -> anyhow::Result<Config> {
+        Config::builder().outdir(outdir).sourcedir(sourcedir).templatedirs(vec![templatedir.into()]).build()
+    }
+
+    #[test]
+    fn test_validate_default() {
+        assert!(config(".dist", "source", "template").is_ok());
+    }
+
+    #[test]
+    fn test_validate_outdir_holds_source() {
+        assert!(config(".", "source", "template").is_err());
+        assert!(config("source", "source", "template").is_err());
+        assert!(config("source/out", "source", "template").is_err());
+        assert!(config("template/out", "source", "template").is_err());
+    }
+
+    #[test]
+    fn test_validate_template_is_source() {
+        assert!(config(".dist", "source", "./source").is_err());
+    }
+
+    #[test]
+    fn test_validate_builddir() {
+        let mut config = config(".dist", "source", "template").unwrap();
+        config.builddir = PathBuf::from("source");
+        assert!(config.validate().is_err());
+        config.builddir = PathBuf::from(".dist/build");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_namespace() {
+        assert!(check_namespace("archive").is_ok());
+        assert!(check_namespace("old-quotes_2").is_ok());
+        assert!(check_namespace("").is_err());
+        assert!(check_namespace("ref").is_err());
+        assert!(check_namespace("a/b").is_err());
+        assert!(check_namespace("..").is_err());
+    }
+
+    #[test]
+    fn test_check_output_name() {
+        assert!(check_output_name("taxonomy", "tags").is_ok());
+        assert!(check_output_name("taxonomy", "").is_err());
+        assert!(check_output_name("feed", "a/b").is_err());
+        assert!(Config::builder().taxonomy("../tags", Default::default()).build().is_err());
+        assert!(Config::builder().feed("../feed", Default::default()).build().is_err());
+        assert!(Config::builder().feed("sitemap", Default::default()).build().is_err());
+    }
+
+    #[test]
+    fn test_check_prefix() {
+        assert!(check_prefix("/blog/").is_ok());
+        assert!(check_prefix("/my blog/").is_err());
+        assert!(check_prefix("https://example.com/").is_err());
+        assert!(check_prefix("/../").is_err());
+        assert!(check_prefix("/a?b/").is_err());
+    }
+}
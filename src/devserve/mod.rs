@@ -2,7 +2,6 @@ use axum::{
     Router,
     routing::any_service,
 };
-use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tower_livereload::LiveReloadLayer;
 use tracing::info;
@@ -14,7 +13,8 @@ pub async fn run(config: &Config, livereload: LiveReloadLayer) -> anyhow::Result
     let website_dir = config.outdir.canonicalize()?;
     info!("serve website_dir: {}", &website_dir.display());
 
-    let addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 3456));
+    let addr = config.addr;
+    info!("serving on http://{}", addr);
 
     let app = Router::new()
         .fallback(any_service(BareUrlServeDir::new(&website_dir)))
@@ -1,6 +1,9 @@
+pub mod check;
 pub mod config;
 pub mod devserve;
 pub mod setup;
+pub mod starter;
+pub mod theme;
 
 pub mod util;
 pub mod web;
@@ -15,6 +18,7 @@ mod tests {
 
 #[test]
 // tests a sample app to verify that assets are copied into outdir when building
+#[allow(deprecated)]
 fn test_sample_template() {
     let config = Config::new("src/test/sample-template/.dist",
                                     "src/test/sample-template/source",
@@ -34,6 +38,7 @@ fn test_sample_template() {
 
 }
 
+#[allow(deprecated)]
 fn audio_sample_config(transcript_opt: Transcript) -> Config{
     Config::new("src/test/sample-audio/.dist",
                                     "src/test/sample-audio/source",
@@ -2,69 +2,206 @@
 use anyhow;
 use tracing::info;
 
+mod check;
 mod config;
 use config::*;
 mod watch;
 mod devserve;
 mod setup;
+mod starter;
+mod theme;
 
 mod util;
 mod web;
 
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
     Dev,
-    Build
+    Build,
+    /// remove outdir and builddir, including the build cache
+    Clean,
+    /// report links and assets in the built site that don't exist
+    Check {
+        /// exit with an error if any link is dangling, e.g. in CI
+        #[arg(long)]
+        exit_code: bool,
+    },
+    /// create a new project with a starter site
+    New {
+        /// directory for the new project, must be empty or not exist
+        dir: PathBuf,
+
+        /// starter site to copy
+        #[arg(short, long, value_enum, default_value_t = starter::Starter::Markdown)]
+        starter: starter::Starter,
+    },
+    /// copy the built-in theme into templatedir for customization
+    EjectTheme {
+        /// replace files that already exist in templatedir
+        #[arg(long)]
+        force: bool,
+    },
+    /// inspect project configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// print merged settings and where each value came from
+    Show {
+        /// show settings as used by this command
+        #[arg(long, value_enum, default_value_t = Mode::Build)]
+        mode: Mode,
+    }
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// directory path for markdown source files
-    #[clap(short, long, value_parser, default_value = "source")]
-    indir: String,
+    /// directory path for markdown source files [default: source]
+    #[clap(short, long, value_parser)]
+    indir: Option<PathBuf>,
+
+    /// destination path for html [default: .dist]
+    #[clap(short, long, value_parser)]
+    outdir: Option<PathBuf>,
+
+    /// directory path for template files [default: template]
+    /// repeat for layers: later ones replace files with the same path in earlier ones
+    #[clap(short, long, value_parser)]
+    templatedir: Vec<PathBuf>,
+
+    /// directory path for ref files [default: sibling of indir]
+    #[clap(long, value_parser)]
+    refdir: Option<PathBuf>,
+
+    /// shared ref directory with its own namespace (repeatable):
+    /// its refs are partials named <NAME>/ref/<file>.html
+    #[clap(long, value_name = "NAME=DIR", value_parser)]
+    refroot: Vec<String>,
+
+    /// directory path for intermediate build files [default: sibling of indir]
+    #[clap(long, value_parser)]
+    builddir: Option<PathBuf>,
+
+    /// path prefix: change if deploying somewhere that is not root path [default: ""]
+    #[clap(short, long, value_parser)]
+    prefix: Option<String>,
+
+    /// annotate transcript option [default: static]
+    #[clap(short, long, value_enum)]
+    annotate: Option<Transcript>,
 
-    /// destination path for html
-    #[clap(short, long, value_parser, default_value = ".dist")]
-    outdir: String,
+    /// dev server address [default: 127.0.0.1:3456]
+    #[clap(long, value_parser)]
+    addr: Option<std::net::SocketAddr>,
 
-    /// directory path for template files
-    #[clap(short, long, value_parser, default_value = "template")]
-    templatedir: String,
+    /// worker threads for rendering [default: one per cpu]
+    #[clap(short, long, value_parser)]
+    jobs: Option<usize>,
 
-    /// path prefix: change if deploying somewhere that is not root path
-    #[clap(short, long, value_parser, default_value = "")]
-    prefix: String,
+    /// build profile from altwebgen.toml, also set by ALTWEBGEN_PROFILE
+    /// [default: dev for 'dev', production for 'build']
+    #[clap(long, value_parser)]
+    profile: Option<String>,
 
-    /// annotate transcript option
-    #[clap(short, long, value_parser, default_value = "static")]
-    annotate: String,
+    /// set a site attribute, replacing the one in _site.yaml (repeatable)
+    /// also set by ALTWEBGEN_SITE_<key>=<value>
+    #[clap(long = "set", value_name = "KEY=VALUE", value_parser)]
+    set: Vec<String>,
+
+    /// render pages with `draft: true` [default: only for 'dev']
+    #[clap(long)]
+    drafts: bool,
+
+    /// render pages with a `date` after now [default: only for 'dev']
+    #[clap(long)]
+    future: bool,
+
+    /// render pages with an `expires` date before now [default: only for 'dev']
+    #[clap(long)]
+    expired: bool,
+
+    /// render every page, ignoring the build cache from previous builds
+    #[clap(long)]
+    no_cache: bool,
+
+    /// stop at the first failing file instead of reporting all of them
+    #[clap(long)]
+    fail_fast: bool,
 
     #[command(subcommand)]
     command: Option<Command>,
 
 }
 
-fn cli_config(cli: &Cli) -> Config {
+// project settings (altwebgen.toml), overridden by any CLI flags
+fn cli_settings(cli: &Cli) -> anyhow::Result<Settings> {
+    let mut settings = Settings::load()?;
+    if let Some(indir) = &cli.indir {
+        settings.sourcedir.set(indir.clone(), Origin::Cli);
+    }
+    if let Some(outdir) = &cli.outdir {
+        settings.outdir.set(outdir.clone(), Origin::Cli);
+    }
+    if !cli.templatedir.is_empty() {
+        settings.templatedirs.set(cli.templatedir.clone(), Origin::Cli);
+    }
+    if let Some(refdir) = &cli.refdir {
+        settings.refdir = Some(Setting::new(refdir.clone(), Origin::Cli));
+    }
+    for assignment in &cli.refroot {
+        settings.set_refroot(assignment, Origin::Cli)?;
+    }
+    if let Some(builddir) = &cli.builddir {
+        settings.builddir = Some(Setting::new(builddir.clone(), Origin::Cli));
+    }
+    if let Some(prefix) = &cli.prefix {
+        settings.prefix.set(prefix.clone(), Origin::Cli);
+    }
+    if let Some(annotate) = cli.annotate {
+        settings.transcript.set(annotate, Origin::Cli);
+    }
+    if let Some(addr) = cli.addr {
+        settings.addr.set(addr, Origin::Cli);
+    }
+    if let Some(jobs) = cli.jobs {
+        settings.jobs.set(jobs, Origin::Cli);
+    }
+    if let Some(profile) = &cli.profile {
+        settings.profile = Some(Setting::new(profile.clone(), Origin::Cli));
+    }
+    if cli.drafts {
+        settings.drafts = Some(Setting::new(true, Origin::Cli));
+    }
+    if cli.future {
+        settings.future = Some(Setting::new(true, Origin::Cli));
+    }
+    if cli.expired {
+        settings.expired = Some(Setting::new(true, Origin::Cli));
+    }
+    for assignment in &cli.set {
+        settings.set_site(assignment, Origin::Cli)?;
+    }
+    Ok(settings)
+}
+
+fn cli_config(cli: &Cli, settings: &Settings) -> anyhow::Result<Config> {
     assert!(cli.command.is_some()); // programmer error, UI should enforce
     let mode = match cli.command {
         Some(Command::Dev) => Mode::Dev,
         _ => Mode::Build
     };
-
-    let annotate_option = match cli.annotate.as_str() {
-        "on" => Transcript::On,
-        "off" => Transcript::Off,
-        _ => Transcript::Static
-    };
-
-    Config::new(&*cli.outdir,
-                &*cli.indir,
-                &*cli.templatedir,
-                &*cli.prefix,
-                mode, annotate_option )
+    settings.builder(mode)?
+        .cache(!cli.no_cache)
+        .fail_fast(cli.fail_fast)
+        .build()
 }
 
 #[tokio::main]
@@ -76,13 +213,49 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     match cli.command {
         None => println!("\nuse command 'dev' for  watch server or 'build' for generating static files\n\n"),
+        Some(Command::New { ref dir, starter }) => {
+            starter::create(dir, starter)?;
+            println!("created new project in {}, to start the dev server:\n  cd {}\n  altwebgen dev",
+                dir.display(), dir.display());
+        },
         Some(ref cmd) => {
-            let config:Config = cli_config(&cli);
+            let settings = cli_settings(&cli)?;
             match cmd {
-                Command::Dev => watch::run(&config).await?,
+                Command::Config { command: ConfigCommand::Show { mode } } => {
+                    print!("{}", settings.with_profile(*mode)?)
+                },
+                Command::EjectTheme { force } => {
+                    let templatedir = settings.templatedir();
+                    let written = theme::write_to(&templatedir, *force)?;
+                    for path in &written {
+                        println!("  {}", path.display());
+                    }
+                    println!("wrote {} of {} theme files to {}",
+                        written.len(), theme::files().len(), templatedir.display());
+                },
+                Command::Dev => {
+                    let config:Config = cli_config(&cli, &settings)?;
+                    setup::init_workers(&config)?;
+                    watch::run(&config).await?
+                },
                 Command::Build => {
+                    let config:Config = cli_config(&cli, &settings)?;
+                    setup::init_workers(&config)?;
                     let _hbs = setup::init_and_build(&config)?;
-                }
+                },
+                Command::Clean => {
+                    let config:Config = settings.config(Mode::Build)?;
+                    setup::clean(&config)?;
+                },
+                Command::Check { exit_code } => {
+                    let config:Config = settings.config(Mode::Build)?;
+                    let report = check::check(&config)?;
+                    println!("{}", report);
+                    if *exit_code && !report.dangling.is_empty() {
+                        anyhow::bail!("{} dangling links", report.dangling.len());
+                    }
+                },
+                Command::New { .. } => unreachable!("handled above")
             }
         }
     };
@@ -1,4 +1,7 @@
-use tracing::info;
+use anyhow::Context as _;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+use walkdir::WalkDir;
 
 use crate::{
     config::{Config, Context},
@@ -6,28 +9,184 @@ use crate::{
     web,
 };
 
+// builds into a staging directory next to outdir and swaps it in on success,
+// so a failed build leaves the previous site in place
 pub fn clean_build(config: &Config) -> anyhow::Result<Context> {
-    config.create_source_dirs()?;
-    clean_and_recreate_dir(&config.outdir)?;
-
-    match web::template::init(config) {
-        Err(e) => return Err(e.context("setting up templates failed")),
-        Ok(context) => {
-            if let Err(e) = web::process_files(&context) {
-                return Err(e.context("build failure"))
-            } else {
-                   info!("...build compelte!");
-            }
+    config.layout().ensure_dirs()?;
+    let mut staged = config.clone();
+    staged.outdir = outdir_sibling(&config.outdir, "staging");
+    clean_and_recreate_dir(&staged.outdir)?;
+    // with the cache, unchanged outputs are kept and only stale ones rewritten
+    if config.cache {
+        copy_outputs(&config.outdir, &staged.outdir)?;
+    }
+
+    let built = match build(&staged) {
+        Ok(built) => built,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&staged.outdir);
+            return Err(e);
+        }
+    };
+    swap_outdir(&staged.outdir, &config.outdir)?;
+    web::cache::commit(config)?;
+    web::manifest::commit(config)?;
+    info!("...build compelte!");
+    Ok(moved_to_outdir(built, config))
+}
+
+// the build's templates and pages, with outpaths in outdir instead of staging
+fn moved_to_outdir<'a>(built: Context<'_>, config: &'a Config) -> Context<'a> {
+    let outpaths = built.outpaths.moved(&built.config.outdir, &config.outdir);
+    let mut context = Context {
+        config,
+        hbs: built.hbs,
+        pages: built.pages,
+        site: built.site,
+        outpaths: Default::default(),
+    };
+    web::template::rebind(&mut context, outpaths);
+    context
+}
+
+// templates and pages as the last build left them, also after a failed build
+pub fn context(config: &Config) -> anyhow::Result<Context<'_>> {
+    let (pages, outpaths) = web::pages::collect_all(config)?;
+    let mut context = Context {
+        config,
+        hbs: web::template::register(config)?,
+        pages: Vec::new(),
+        site: Default::default(),
+        outpaths: Default::default(),
+    };
+    context.set_pages(pages);
+    web::template::set_outpaths(&mut context, outpaths);
+    Ok(context)
+}
+
+fn build(config: &Config) -> anyhow::Result<Context<'_>> {
+    // failures in refs and pages are collected, the build goes on without them
+    let mut diagnostics = web::diagnostic::Diagnostics::new(config.fail_fast);
+    match web::template::init(config, &mut diagnostics) {
+        Err(e) => Err(e.context("setting up templates failed")),
+        Ok(mut context) => {
+            diagnostics.check(web::process_files(&mut context))
+                .map_err(|e| e.context("build failure"))?;
+            diagnostics.finish()?;
             Ok(context)
         }
     }
 }
 
+// e.g. .dist.staging, in the same parent so a rename can't cross filesystems
+fn outdir_sibling(outdir: &Path, suffix: &str) -> PathBuf {
+    let name = outdir.file_name().unwrap_or_default().to_string_lossy();
+    outdir.with_file_name(format!("{}.{}", name, suffix))
+}
+
+// hard links, so staging doesn't copy the whole site; the build replaces
+// an output rather than writing through it, see PathExt::prepare_output
+fn copy_outputs(outdir: &Path, staging: &Path) -> anyhow::Result<()> {
+    for entry in WalkDir::new(outdir) {
+        let entry = entry?;
+        let dest = staging.join(entry.path().strip_prefix(outdir)?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest)?;
+        } else if std::fs::hard_link(entry.path(), &dest).is_err() {
+            std::fs::copy(entry.path(), &dest)
+                .with_context(|| format!("failed to stage {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+// the new site and the old one trade places in one rename, so outdir is never
+// missing or half moved; then staging holds the old site and is deleted
+fn swap_outdir(staging: &Path, outdir: &Path) -> anyhow::Result<()> {
+    if outdir.exists() {
+        match exchange(staging, outdir) {
+            Ok(()) => {
+                std::fs::remove_dir_all(staging)?;
+                return Ok(());
+            },
+            Err(e) => debug!("can't swap {} and {} in one step ({}), moving the old site aside",
+                staging.display(), outdir.display(), e),
+        }
+    }
+    replace_outdir(staging, outdir)
+}
+
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    // renameat2 through syscall, libc only wraps it for glibc
+    let result = unsafe {
+        libc::syscall(libc::SYS_renameat2, libc::AT_FDCWD, a.as_ptr(), libc::AT_FDCWD, b.as_ptr(), libc::RENAME_EXCHANGE)
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(_a: &Path, _b: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+// without an exchange, e.g. on a filesystem that doesn't support it:
+// a directory can't be renamed over a non-empty one, so move the old site aside,
+// move the new one in, then delete the old one, restoring it if the move fails
+fn replace_outdir(staging: &Path, outdir: &Path) -> anyhow::Result<()> {
+    let previous = outdir_sibling(outdir, "previous");
+    if previous.exists() {
+        std::fs::remove_dir_all(&previous)?;
+    }
+    if outdir.exists() {
+        std::fs::rename(outdir, &previous)
+            .with_context(|| format!("failed to move {} aside", outdir.display()))?;
+    }
+    if let Err(e) = std::fs::rename(staging, outdir) {
+        if previous.exists() {
+            std::fs::rename(&previous, outdir)?;
+        }
+        return Err(anyhow::Error::new(e)
+            .context(format!("failed to move {} to {}", staging.display(), outdir.display())));
+    }
+    if previous.exists() {
+        std::fs::remove_dir_all(&previous)?;
+    }
+    Ok(())
+}
+
+// remove everything a build generates, so the next one starts from scratch
+pub fn clean(config: &Config) -> anyhow::Result<()> {
+    for dir in [&config.outdir, &config.builddir] {
+        if dir.exists() {
+            info!("removing {}", dir.display());
+            std::fs::remove_dir_all(dir)
+                .with_context(|| format!("failed to delete directory: {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+// size the worker pool used for rendering, called at most once per process
+pub fn init_workers(config: &Config) -> anyhow::Result<()> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(config.jobs)
+        .build_global()?;
+    info!("rendering with {} worker threads", rayon::current_num_threads());
+    Ok(())
+}
+
 // initial setup, called only once
 pub fn init_and_build(config: &Config) -> anyhow::Result<Context> {
     info!("init: start");
     info!("      working directory {}", get_current_working_dir()?.display());
-    config.create_source_dirs()?;
 
     let context = clean_build(&config)?;
     info!("init: complete");
@@ -0,0 +1,133 @@
+// starter projects for `altwebgen new`
+// files are compiled into the binary, most of them shared with `samples`
+use anyhow::bail;
+use clap::ValueEnum;
+use std::path::Path;
+use tracing::info;
+
+use crate::util::PathExt;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Starter {
+    /// plain markdown pages, modelled on samples/markdown
+    Markdown,
+    /// text with audio, modelled on samples/media
+    Media,
+    /// pages with a navigation sidebar, modelled on samples/sidebar
+    Sidebar,
+}
+
+// (path in new project, contents)
+type StarterFile = (&'static str, &'static [u8]);
+
+// every starter: site attributes, example ref with audio and transcript,
+// the audio playback partial and a page that uses it
+static COMMON: &[StarterFile] = &[
+    ("altwebgen.toml", include_bytes!("common/altwebgen.toml")),
+    ("source/hypertext.md.hbs", include_bytes!("common/hypertext.md.hbs")),
+    ("source/audio.css", include_bytes!("../../samples/media/source/audio.css")),
+    ("source/icon/pause24.png", include_bytes!("../../samples/media/source/icon/pause24.png")),
+    ("source/icon/play24.png", include_bytes!("../../samples/media/source/icon/play24.png")),
+    ("source/icon/rewind24.png", include_bytes!("../../samples/media/source/icon/rewind24.png")),
+    ("template/_audioplayback.hbs", include_bytes!("../../samples/media/template/_audioplayback.hbs")),
+    ("template/words.hbs", include_bytes!("../../samples/media/template/words.hbs")),
+    ("ref/hypertext.md", include_bytes!("../../samples/media/ref/hypertext.md")),
+    ("ref/hypertext.mp3", include_bytes!("../../samples/media/ref/hypertext.mp3")),
+    ("ref/hypertext.transcript.json", include_bytes!("../../samples/media/ref/hypertext.transcript.json")),
+];
+
+static MARKDOWN: &[StarterFile] = &[
+    ("source/_site.yaml", include_bytes!("common/_site.yaml")),
+    ("source/index.md", include_bytes!("markdown/index.md")),
+    ("template/default.hbs", include_bytes!("../../samples/markdown/template/default.hbs")),
+    ("template/home.hbs", include_bytes!("../../samples/markdown/template/home.hbs")),
+];
+
+static MEDIA: &[StarterFile] = &[
+    ("source/_site.yaml", include_bytes!("common/_site.yaml")),
+    ("source/index.md.hbs", include_bytes!("media/index.md.hbs")),
+    ("template/default.hbs", include_bytes!("../../samples/media/template/default.hbs")),
+];
+
+static SIDEBAR: &[StarterFile] = &[
+    ("source/_site.yaml", include_bytes!("sidebar/_site.yaml")),
+    ("source/index.md", include_bytes!("sidebar/index.md")),
+    ("source/sidebar.css", include_bytes!("sidebar/sidebar.css")),
+    ("template/default.hbs", include_bytes!("sidebar/default.hbs")),
+];
+
+impl Starter {
+    fn files(&self) -> impl Iterator<Item = &'static StarterFile> {
+        let files = match self {
+            Starter::Markdown => MARKDOWN,
+            Starter::Media => MEDIA,
+            Starter::Sidebar => SIDEBAR,
+        };
+        COMMON.iter().chain(files.iter())
+    }
+}
+
+// write a complete starter project into dir, which must be empty or not exist
+pub fn create<P: AsRef<Path>>(dir: P, starter: Starter) -> anyhow::Result<()> {
+    let dir = dir.as_ref();
+    if dir.exists() && std::fs::read_dir(dir)?.next().is_some() {
+        bail!("can't create new project, directory not empty: {}", dir.display());
+    }
+    info!("new project: {} ({:?})", dir.display(), starter);
+    for (rel_path, contents) in starter.files() {
+        let path = dir.join(rel_path);
+        path.create_all_parent_dir()?;
+        std::fs::write(&path, contents)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::*, setup};
+
+    #[allow(deprecated)]
+    fn build_starter(starter: Starter) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("altwebgen-starter-{:?}", starter));
+        let _ = std::fs::remove_dir_all(&dir);
+        create(&dir, starter).unwrap();
+
+        let config = Config::new(&dir.join(".dist").to_string_lossy(),
+                                 &dir.join("source").to_string_lossy(),
+                                 &dir.join("template").to_string_lossy(),
+                                 "", Mode::Build, Transcript::Static);
+        setup::init_and_build(&config).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_new_markdown() {
+        let dir = build_starter(Starter::Markdown);
+        assert!(dir.join(".dist/index.html").exists());
+        assert!(dir.join(".dist/hypertext.html").exists());
+        assert!(dir.join(".dist/media/hypertext.mp3").exists());
+    }
+
+    #[test]
+    fn test_new_media() {
+        let dir = build_starter(Starter::Media);
+        let html = std::fs::read_to_string(dir.join(".dist/index.html")).unwrap();
+        assert!(html.contains("<audio"));
+    }
+
+    #[test]
+    fn test_new_sidebar() {
+        let dir = build_starter(Starter::Sidebar);
+        let html = std::fs::read_to_string(dir.join(".dist/index.html")).unwrap();
+        assert!(html.contains("<a href=\"hypertext.html\">Hypertext</a>"));
+    }
+
+    #[test]
+    fn test_new_not_empty() {
+        let dir = std::env::temp_dir().join("altwebgen-starter-not-empty");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep.txt"), "keep").unwrap();
+        assert!(create(&dir, Starter::Markdown).is_err());
+    }
+}
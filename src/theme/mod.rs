@@ -0,0 +1,101 @@
+// built-in default theme, compiled into the binary
+// templates and assets here sit underneath the project's templatedir:
+// any file in templatedir with the same relative path replaces the built-in one
+use std::path::{Path, PathBuf};
+use tracing::{info, trace};
+
+use crate::util::PathExt;
+
+pub static DEFAULT_HBS: &[u8] = include_bytes!("default.hbs");
+pub static WORDS_HBS: &[u8] = include_bytes!("words.hbs");
+pub static AUDIOPLAYBACK_HBS: &[u8] = include_bytes!("_audioplayback.hbs");
+pub static TAXONOMY_TERM_HBS: &[u8] = include_bytes!("_taxonomy_term.hbs");
+pub static TAXONOMY_INDEX_HBS: &[u8] = include_bytes!("_taxonomy_index.hbs");
+pub static AUDIOPLAYBACK_JS: &[u8] = include_bytes!("audioplayback.js");
+pub static AUDIO_CSS: &[u8] = include_bytes!("audio.css");
+pub static PLAY_PNG: &[u8] = include_bytes!("icon/play24.png");
+pub static PAUSE_PNG: &[u8] = include_bytes!("icon/pause24.png");
+pub static REWIND_PNG: &[u8] = include_bytes!("icon/rewind24.png");
+
+/// Every theme file, as (path relative to the template directory, contents)
+pub fn files() -> [(&'static str, &'static [u8]); 10] {
+    [
+        ("default.hbs", DEFAULT_HBS),
+        ("words.hbs", WORDS_HBS),
+        ("_audioplayback.hbs", AUDIOPLAYBACK_HBS),
+        ("_taxonomy_term.hbs", TAXONOMY_TERM_HBS),
+        ("_taxonomy_index.hbs", TAXONOMY_INDEX_HBS),
+        ("theme/audioplayback.js", AUDIOPLAYBACK_JS),
+        ("theme/audio.css", AUDIO_CSS),
+        ("theme/icon/play24.png", PLAY_PNG),
+        ("theme/icon/pause24.png", PAUSE_PNG),
+        ("theme/icon/rewind24.png", REWIND_PNG),
+    ]
+}
+
+/// Write the theme files into dir.
+/// Existing files are kept unless overwrite is set.
+/// Returns paths of files that were written.
+pub fn write_to<P: AsRef<Path>>(dir: P, overwrite: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let mut written = Vec::new();
+    for (rel_path, contents) in files() {
+        let path = dir.join(rel_path);
+        if path.exists() && !overwrite {
+            info!("theme: keeping existing {}", path.display());
+            continue;
+        }
+        trace!("theme: writing {}", path.display());
+        path.create_all_parent_dir()?;
+        std::fs::write(&path, contents)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::*, setup};
+
+    #[test]
+    fn test_write_to_keeps_existing() {
+        let dir = std::env::temp_dir().join("altwebgen-theme-eject");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("default.hbs"), "mine").unwrap();
+
+        let written = write_to(&dir, false).unwrap();
+        assert_eq!(written.len(), files().len() - 1);
+        assert_eq!(std::fs::read_to_string(dir.join("default.hbs")).unwrap(), "mine");
+        assert!(dir.join("theme/audioplayback.js").exists());
+
+        write_to(&dir, true).unwrap();
+        assert_eq!(std::fs::read(dir.join("default.hbs")).unwrap(), DEFAULT_HBS);
+    }
+
+    #[test]
+    // a project with no templates of its own builds with the built-in theme
+    #[allow(deprecated)]
+    fn test_build_with_empty_templatedir() {
+        let dir = std::env::temp_dir().join("altwebgen-theme-default");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source/sub")).unwrap();
+        std::fs::write(dir.join("source/index.md"), "# Home").unwrap();
+        std::fs::write(dir.join("source/sub/page.md"), "---\nlayout: words\n---\n# Sub").unwrap();
+
+        let config = Config::new(&dir.join(".dist").to_string_lossy(),
+                                 &dir.join("source").to_string_lossy(),
+                                 &dir.join("template").to_string_lossy(),
+                                 "", Mode::Build, Transcript::Off);
+        setup::init_and_build(&config).unwrap();
+
+        let html = std::fs::read_to_string(dir.join(".dist/index.html")).unwrap();
+        assert!(html.contains("<h1>Home</h1>"));
+        assert!(html.contains("href=\"theme/audio.css\""));
+        let html = std::fs::read_to_string(dir.join(".dist/sub/page.html")).unwrap();
+        assert!(html.contains("src=\"../theme/audioplayback.js\""));
+        assert!(dir.join(".dist/theme/icon/play24.png").exists());
+        assert!(!dir.join(".dist/default.hbs").exists());
+    }
+}
@@ -17,6 +17,18 @@ pub use node_ref::NodeRefExt as NodeRefExt;
 pub mod path;
 pub use self::path::PathExt as PathExt;
 
+// front matter and site attributes: yaml parsed into a json map, so lists,
+// numbers, booleans and nested maps carry through to the template context
+pub type Attributes = serde_json::Map<String, serde_json::Value>;
+
+pub fn parse_yaml_attributes(yaml: &str) -> anyhow::Result<Attributes> {
+    match serde_yaml::from_str(yaml)? {
+        serde_json::Value::Object(map) => Ok(map),
+        serde_json::Value::Null => Ok(Attributes::new()),
+        other => bail!("expected yaml key/value pairs, found: {}", other),
+    }
+}
+
 pub fn read_file_to_string<P: AsRef<Path>>(filepath: P) -> anyhow::Result<String> {
     let path = std::fs::canonicalize(filepath)?;
     trace!("read_file #{}", path.display());
@@ -111,3 +123,36 @@ pub fn clean_and_recreate_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
     std::fs::create_dir(&media_dir)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_yaml_attributes_typed() {
+        let attr = parse_yaml_attributes("
+title: Hello
+draft: true
+weight: 3
+tags: [one, two]
+author:
+  name: Ada
+").unwrap();
+        assert_eq!(attr["title"], json!("Hello"));
+        assert_eq!(attr["draft"], json!(true));
+        assert_eq!(attr["weight"], json!(3));
+        assert_eq!(attr["tags"], json!(["one", "two"]));
+        assert_eq!(attr["author"]["name"], json!("Ada"));
+    }
+
+    #[test]
+    fn test_parse_yaml_attributes_empty() {
+        assert!(parse_yaml_attributes("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_yaml_attributes_not_a_map() {
+        assert!(parse_yaml_attributes("- one\n- two").is_err());
+    }
+}
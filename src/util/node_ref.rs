@@ -1,5 +1,6 @@
 use anyhow::bail;
 use kuchikiki::{ElementData, NodeDataRef, NodeRef};
+use std::collections::BTreeSet;
 use markup5ever::{interface::QualName, namespace_url, ns, LocalName};
 
 #[allow(unused)]
@@ -23,6 +24,12 @@ pub trait NodeRefExt {
 
     /// Creates a new HTML element with the given name and attributes.
     fn new_html_element(name: &str, attributes: Vec<(&str, &str)>) -> NodeRef;
+
+    /// every `href` and `src` in the document, as (attribute, value)
+    fn link_targets(&self) -> Vec<(String, String)>;
+
+    /// fragment targets: `id` of any element and `name` of `<a>`
+    fn anchor_ids(&self) -> BTreeSet<String>;
 }
 impl NodeRefExt for NodeRef {
     fn html(&self) -> Option<NodeDataRef<ElementData>> {
@@ -99,6 +106,39 @@ impl NodeRefExt for NodeRef {
     }
 
 
+    fn link_targets(&self) -> Vec<(String, String)> {
+        let mut targets = Vec::new();
+        for node in self.inclusive_descendants() {
+            if let Some(element) = node.as_element() {
+                let attributes = element.attributes.borrow();
+                for name in ["href", "src"] {
+                    if let Some(value) = attributes.get(name) {
+                        targets.push((name.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+        targets
+    }
+
+    fn anchor_ids(&self) -> BTreeSet<String> {
+        let mut ids = BTreeSet::new();
+        for node in self.inclusive_descendants() {
+            if let Some(element) = node.as_element() {
+                let attributes = element.attributes.borrow();
+                if let Some(id) = attributes.get("id") {
+                    ids.insert(id.to_string());
+                }
+                if &*element.name.local == "a" {
+                    if let Some(name) = attributes.get("name") {
+                        ids.insert(name.to_string());
+                    }
+                }
+            }
+        }
+        ids
+    }
+
     // thanks critter-rs for this utility function
     fn new_html_element(name: &str, attributes: Vec<(&str, &str)>) -> NodeRef {
         use kuchikiki::{Attribute, ExpandedName};
@@ -144,6 +184,23 @@ mod tests {
 
 
 
+    #[test]
+    fn test_link_targets_and_anchor_ids() {
+        use kuchikiki::traits::*;
+        let document: NodeRef = kuchikiki::parse_html().one(r##"
+            <link rel="stylesheet" href="site.css">
+            <h2 id="notes">Notes</h2><a name="top" href="#notes">up</a>
+            <img src="a.png">
+        "##);
+        assert_eq!(document.link_targets(), vec![
+            (String::from("href"), String::from("site.css")),
+            (String::from("href"), String::from("#notes")),
+            (String::from("src"), String::from("a.png")),
+        ]);
+        let ids: Vec<String> = document.anchor_ids().into_iter().collect();
+        assert_eq!(ids, vec!["notes", "top"]);
+    }
+
     #[test]
     fn test_new_html_element_no_attrs() {
         let node_ref = NodeRef::new_html_element("head", Vec::new());
@@ -10,6 +10,9 @@ pub trait PathExt {
     // given a path, ensure that all parent directories of that path exist
     // and create any that don't exist
     fn create_all_parent_dir(&self) -> std::io::Result<()>;
+    // before writing an output: a staged output can be a hard link to the
+    // published one (see setup::copy_outputs), so it's removed, not written through
+    fn prepare_output(&self) -> std::io::Result<()>;
     fn get_ext(&self) -> Option<Cow<'static, str>>;
     fn get_ext_str(&self) -> Option<&str>;
     fn mimetype(&self) -> Option<Mime>;
@@ -25,6 +28,14 @@ impl PathExt for Path {
         Ok(())
     }
 
+    fn prepare_output(&self) -> std::io::Result<()> {
+        self.create_all_parent_dir()?;
+        match std::fs::remove_file(self) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     fn get_ext_str(&self) -> Option<&str> {
         if let Some(ext_osstr) = self.extension() {
             ext_osstr.to_str()
@@ -1,27 +1,72 @@
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::{info, error};
 use tower_livereload::LiveReloadLayer;
 
 use crate::{
-    config::Config,
+    config::{Config, Context},
     devserve,
     setup,
-    util::*,
-    web
+    web::{self, deps::DepGraph, diagnostic::{BuildFailure, Diagnostics}},
 };
 
 mod watch_files;
 use watch_files::detect_changes;
 
 
+// the watcher may report absolute (or canonical) paths for a watched
+// directory given as a relative path: rewrite them in terms of the config path
+fn config_path(watched: &[PathBuf], path: &Path) -> PathBuf {
+    for dir in watched {
+        if path.starts_with(dir) {
+            return path.to_path_buf();
+        }
+        for base in [std::env::current_dir().ok().map(|cwd| cwd.join(dir)), dir.canonicalize().ok()].into_iter().flatten() {
+            if let Ok(rel_path) = path.strip_prefix(&base) {
+                return dir.join(rel_path);
+            }
+        }
+    }
+    path.to_path_buf()
+}
+
+// pages that can't be read are reported and left out of the graph,
+// the dev server keeps running until they are fixed
+fn scan_deps(config: &Config) -> DepGraph {
+    let mut diagnostics = Diagnostics::new(false);
+    let deps = DepGraph::scan(config, &mut diagnostics).unwrap_or_else(|e| {
+        error!("dependency scan failed: {:?}", e);
+        DepGraph::default()
+    });
+    if let Err(e) = diagnostics.finish() {
+        error!("{}", e);
+    }
+    deps
+}
+
+// a build that fails on some pages keeps the server up with the rest,
+// any other failure stops it
+fn build_or_report<'a>(config: &'a Config, build: impl FnOnce(&'a Config) -> anyhow::Result<Context<'a>>)
+    -> anyhow::Result<Context<'a>> {
+    match build(config) {
+        Ok(context) => Ok(context),
+        Err(e) => match e.downcast_ref::<BuildFailure>() {
+            Some(failure) => {
+                error!("{}", failure);
+                setup::context(config)
+            },
+            None => Err(e),
+        },
+    }
+}
+
 pub async fn run(config: &Config) -> anyhow::Result<()> {
-    let mut context= setup::init_and_build(&config)?;
-    let mut source_watch = Vec::new();
-    source_watch.push(config.sourcedir.clone());
-    let mut template_watch = Vec::new();
-    template_watch.push(config.templatedir.clone());
-    template_watch.push(PathBuf::from("ref"));
+    let mut context = build_or_report(config, setup::init_and_build)?;
+    let mut deps = scan_deps(config);
+    let mut watch = Vec::new();
+    watch.push(config.sourcedir.clone());
+    watch.extend(config.templatedirs.iter().cloned());
+    watch.extend(config.ref_roots().iter().map(|(_, dir)| dir.to_path_buf()));
 
     // Tower Layer to handle browser/client comms
     let livereload = LiveReloadLayer::new();
@@ -34,27 +79,29 @@ pub async fn run(config: &Config) -> anyhow::Result<()> {
         _ = async {
             loop {
                 info!("watching for changes...");
-                tokio::select! {
-                    source_result = detect_changes(&source_watch) => {
-                        info!("source watcher result {:?}", source_result);
-                        clean_and_recreate_dir(&config.outdir)?;
-                        if let Err(e) = web::process_files(&context) {
-                            error!("process_files failed: {:?}", e);
-                        } else {
-                            reloader.reload();
-                        }
-                    },
-                    template_result = detect_changes(&template_watch) => {
-                        info!("template watcher result {:?}", template_result);
-                        match setup::clean_build(&config) {
-                            Err(e) => {error!("build failed: {:?}", e); break},
-                            Ok(new_context) => {
-                                context = new_context;
-                                reloader.reload();
-                            }
+                let changed = match detect_changes(&watch).await {
+                    Ok(paths) => paths.iter().map(|path| config_path(&watch, path)).collect::<Vec<_>>(),
+                    Err(e) => {error!("watcher failed: {:?}", e); continue},
+                };
+                info!("watcher result {:?}", changed);
+                if let Err(e) = web::process_changes(&mut context, &mut deps, &changed) {
+                    if let Some(failure) = e.downcast_ref::<BuildFailure>() {
+                        // everything else was rebuilt, reload and wait for a fix
+                        error!("{}", failure);
+                        reloader.reload();
+                        continue;
+                    }
+                    // state may be partly updated: start over from a clean build
+                    error!("incremental build failed: {:?}", e);
+                    match build_or_report(config, setup::clean_build) {
+                        Err(e) => {error!("build failed: {:?}", e); break},
+                        Ok(new_context) => {
+                            context = new_context;
+                            deps = scan_deps(config);
                         }
                     }
                 }
+                reloader.reload();
             }
             anyhow::Result::<()>::Ok(())
         } => {}
@@ -0,0 +1,365 @@
+// build cache, kept in builddir between runs:
+// for each source, a hash of everything its output depends on
+// (content, the template set and config that changes rendering),
+// and what its last render looked up: the sources it linked to and,
+// if its templates list them, site.pages; so a build only renders
+// sources whose hash changed or whose lookups would come out differently
+use anyhow::Context as _;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::{
+    config::Config,
+    util::Attributes,
+    web::{deps::DepGraph, document::{Document, Outpaths}, paginate},
+};
+
+const CACHE_FILE: &str = "cache.json";
+// written by a build, replaces the cache once its outputs are in outdir
+const PENDING_FILE: &str = "cache.json.pending";
+const REF_CACHE_FILE: &str = "refs.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    // relative to outdir, which is a staging directory during a build
+    outpath: PathBuf,
+    // source linked to → its outpath when this page was rendered, relative to outdir
+    #[serde(default)]
+    links: BTreeMap<PathBuf, PathBuf>,
+    // hash of site.pages when this page was rendered, if it lists them
+    #[serde(default)]
+    pages: Option<String>,
+}
+
+thread_local! {
+    // sources linked to by the page being rendered on this thread
+    static LINKS: RefCell<Option<BTreeSet<PathBuf>>> = const { RefCell::new(None) };
+}
+
+// a link to another source, from markdown or the url helper
+pub fn record_link(target: &Path) {
+    LINKS.with(|links| {
+        if let Some(links) = links.borrow_mut().as_mut() {
+            links.insert(target.to_path_buf());
+        }
+    });
+}
+
+// runs a render, returning the sources it linked to
+pub fn recording_links<T>(render: impl FnOnce() -> T) -> (T, BTreeSet<PathBuf>) {
+    let outer = LINKS.with(|links| links.replace(Some(BTreeSet::new())));
+    let result = render();
+    let links = LINKS.with(|links| links.replace(outer)).unwrap_or_default();
+    (result, links)
+}
+
+#[derive(Debug, Default)]
+pub struct BuildCache {
+    // hash of the config and the build template directory, part of every page key
+    base: String,
+    // which templates list site.pages, directly or through partials
+    templates: DepGraph,
+    // hash of site.pages in this build
+    pages: String,
+    // source path → key it was last rendered with
+    entries: BTreeMap<PathBuf, Entry>,
+    // keys computed for this build
+    pending: BTreeMap<PathBuf, Entry>,
+}
+
+impl BuildCache {
+    // previous entries are ignored with --no-cache, so everything is rendered
+    pub fn load(config: &Config) -> anyhow::Result<BuildCache> {
+        let path = config.builddir.join(CACHE_FILE);
+        let entries = if config.cache && path.is_file() {
+            let text = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&text).unwrap_or_else(|e| {
+                warn!("ignoring unreadable build cache {}: {}", path.display(), e);
+                BTreeMap::new()
+            })
+        } else {
+            BTreeMap::new()
+        };
+        let mut templates = DepGraph::default();
+        templates.scan_templates(config)?;
+        Ok(BuildCache {
+            base: base_key(config)?,
+            templates,
+            pages: String::new(),
+            entries,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    // sources whose output is missing or was rendered from something else
+    pub fn stale(&mut self, config: &Config, outpaths: &Outpaths, files: &[PathBuf], pages: &[Attributes]) -> anyhow::Result<Vec<PathBuf>> {
+        self.pages = hash_parts(&[&serde_json::to_vec(pages)?]);
+        let keyed: Vec<(PathBuf, String, PathBuf)> = files.par_iter()
+            .map(|path| {
+                let outpath = outpaths.get(config, path)?.strip_prefix(&config.outdir)?.to_path_buf();
+                let content = std::fs::read(path)?;
+                let key = if Document::from_path(path).is_html_source() {
+                    hash_parts(&[self.base.as_bytes(), path_bytes(path), &content])
+                } else {
+                    hash_parts(&[path_bytes(path), &content])
+                };
+                Ok((path.clone(), key, outpath))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let mut stale = Vec::new();
+        for (path, key, outpath) in keyed {
+            let fresh = match self.entries.get(&path) {
+                Some(before) => {
+                    // moved by a permalink
+                    let previous = config.outdir.join(&before.outpath);
+                    if before.outpath != outpath && previous.is_file() {
+                        std::fs::remove_file(&previous)?;
+                        paginate::remove_pages(&previous, 2)?;
+                    }
+                    before.key == key && before.outpath == outpath
+                        && config.outdir.join(&outpath).exists()
+                        && self.same_lookups(config, outpaths, before)
+                },
+                None => false,
+            };
+            if fresh {
+                let entry = self.entries[&path].clone();
+                self.pending.insert(path, entry);
+            } else {
+                stale.push(path.clone());
+                let entry = Entry { key, outpath, links: BTreeMap::new(), pages: None };
+                self.pending.insert(path, entry);
+            }
+        }
+        info!("build cache: {} of {} files to render", stale.len(), files.len());
+        Ok(stale)
+    }
+
+    // every source the page linked to is still written where it was,
+    // and site.pages is unchanged if the page lists them
+    fn same_lookups(&self, config: &Config, outpaths: &Outpaths, entry: &Entry) -> bool {
+        let links_same = entry.links.iter().all(|(target, before)| {
+            target.is_file() && outpaths.get(config, target)
+                .is_ok_and(|outpath| outpath.strip_prefix(&config.outdir) == Ok(before.as_path()))
+        });
+        links_same && entry.pages.iter().all(|pages| *pages == self.pages)
+    }
+
+    // what a page looked up while it was rendered, see recording_links
+    pub fn rendered(&mut self, config: &Config, outpaths: &Outpaths, source: &Path, links: &BTreeSet<PathBuf>) -> anyhow::Result<()> {
+        let uses_pages = self.templates.uses_pages(&DepGraph::dependencies(source)?);
+        let Some(entry) = self.pending.get_mut(source) else { return Ok(()) };
+        entry.links = links.iter()
+            .map(|target| {
+                let outpath = outpaths.get(config, target)?;
+                Ok((target.clone(), outpath.strip_prefix(&config.outdir)?.to_path_buf()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        entry.pages = uses_pages.then(|| self.pages.clone());
+        Ok(())
+    }
+
+    // outputs of sources that were rendered before but are gone now
+    pub fn remove_deleted(&self, config: &Config) -> anyhow::Result<()> {
+        for (source, entry) in &self.entries {
+            let outpath = config.outdir.join(&entry.outpath);
+            if !self.pending.contains_key(source) && outpath.is_file() {
+                info!("removing {}, source deleted", outpath.display());
+                std::fs::remove_file(&outpath)?;
+                paginate::remove_pages(&outpath, 2)?;
+            }
+        }
+        Ok(())
+    }
+
+    // see commit: only used once the build has succeeded
+    pub fn save(self, config: &Config) -> anyhow::Result<()> {
+        let path = config.builddir.join(PENDING_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(&self.pending)?)
+            .with_context(|| format!("failed to write build cache {}", path.display()))
+    }
+}
+
+// sources each page linked to when the last build rendered it
+pub fn links(config: &Config) -> BTreeMap<PathBuf, BTreeSet<PathBuf>> {
+    let entries: BTreeMap<PathBuf, Entry> = std::fs::read_to_string(config.builddir.join(CACHE_FILE)).ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default();
+    entries.into_iter()
+        .map(|(source, entry)| (source, entry.links.into_keys().collect()))
+        .collect()
+}
+
+// after a build's outputs replaced outdir, its cache replaces the previous one;
+// after a failed build the previous cache still matches outdir
+pub fn commit(config: &Config) -> anyhow::Result<()> {
+    let pending = config.builddir.join(PENDING_FILE);
+    if pending.is_file() {
+        std::fs::rename(&pending, config.builddir.join(CACHE_FILE))?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct RefEntry {
+    namespace: Option<String>,
+    key: String,
+}
+
+// generated ref partials are kept in the build template directory between
+// builds, each with a hash of the files and settings it was made from,
+// so only refs that changed are converted and aligned with their transcript
+#[derive(Debug, Default)]
+pub struct RefCache {
+    // partial, relative to the build template directory → what it was made from
+    entries: BTreeMap<PathBuf, RefEntry>,
+}
+
+impl RefCache {
+    // ignored with --no-cache, so every ref is generated
+    pub fn load(config: &Config) -> anyhow::Result<RefCache> {
+        let path = config.builddir.join(REF_CACHE_FILE);
+        let entries = if config.cache && path.is_file() {
+            let text = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&text).unwrap_or_else(|e| {
+                warn!("ignoring unreadable ref cache {}: {}", path.display(), e);
+                BTreeMap::new()
+            })
+        } else {
+            BTreeMap::new()
+        };
+        Ok(RefCache { entries })
+    }
+
+    // the markdown, audio and transcript of a ref, and the settings that
+    // change its partial: the prefix in the audio url and the transcript mode
+    pub fn key(config: &Config, namespace: Option<&str>, files: &[Option<&Path>]) -> anyhow::Result<String> {
+        let settings = format!("{} {} {} {:?}",
+            env!("CARGO_PKG_VERSION"), config.prefix, config.transcript, namespace);
+        let contents = files.iter()
+            .map(|file| file.map_or(Ok(Vec::new()), std::fs::read))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let mut parts: Vec<&[u8]> = vec![settings.as_bytes()];
+        for (file, content) in files.iter().zip(&contents) {
+            parts.push(file.map(path_bytes).unwrap_or_default());
+            parts.push(content);
+        }
+        Ok(hash_parts(&parts))
+    }
+
+    pub fn is_fresh(&self, partial: &Path, key: &str) -> bool {
+        self.entries.get(partial).is_some_and(|entry| entry.key == key)
+    }
+
+    pub fn insert(&mut self, partial: PathBuf, namespace: Option<&str>, key: String) {
+        self.entries.insert(partial, RefEntry { namespace: namespace.map(str::to_string), key });
+    }
+
+    pub fn remove(&mut self, partial: &Path) {
+        self.entries.remove(partial);
+    }
+
+    // partials of the namespace that weren't generated this time
+    pub fn stale(&self, namespace: Option<&str>, generated: &[PathBuf]) -> Vec<PathBuf> {
+        self.entries.iter()
+            .filter(|(partial, entry)| entry.namespace.as_deref() == namespace && !generated.contains(partial))
+            .map(|(partial, _)| partial.clone())
+            .collect()
+    }
+
+    pub fn save(&self, config: &Config) -> anyhow::Result<()> {
+        let path = config.builddir.join(REF_CACHE_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(&self.entries)?)
+            .with_context(|| format!("failed to write ref cache {}", path.display()))
+    }
+}
+
+// config that changes rendered html, and every file in the build template
+// directory, which holds the layers and generated ref partials; which pages
+// are published changes site.pages, taxonomies change where person links go
+fn base_key(config: &Config) -> anyhow::Result<String> {
+    let settings = format!("{} {} {:?} {:?} {} {} {} {} {} {} {:?} {:?} {:?}",
+        env!("CARGO_PKG_VERSION"), config.prefix, config.permalink, config.site_attr,
+        config.mode, config.transcript, config.profile, config.drafts, config.future,
+        config.expired, config.taxonomies, config.feeds, config.refroots);
+    let mut hasher = Sha256::new();
+    hasher.update(settings.as_bytes());
+    let buildtemplatedir = config.buildtemplatedir();
+    for entry in WalkDir::new(&buildtemplatedir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            hasher.update(path_bytes(entry.path().strip_prefix(&buildtemplatedir)?));
+            hasher.update(std::fs::read(entry.path())?);
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// each part is length-prefixed so that ("ab", "c") and ("a", "bc") differ
+fn hash_parts(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_encoded_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // flipping any setting that changes rendered html renders every page again
+    fn test_base_key_settings() {
+        let dir = std::env::temp_dir().join("altwebgen-cache-base-key");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .builddir(dir.join(".build"))
+            .build().unwrap();
+        std::fs::create_dir_all(config.buildtemplatedir()).unwrap();
+        let base = base_key(&config).unwrap();
+        let changes: [(&str, fn(&mut Config)); 12] = [
+            ("prefix", |c| c.prefix = String::from("/blog/")),
+            ("permalink", |c| c.permalink = Some(String::from("/:slug/"))),
+            ("baseurl", |c| { c.site_attr.insert("baseurl".into(), "https://example.com".into()); }),
+            ("mode", |c| c.mode = crate::config::Mode::Dev),
+            ("transcript", |c| c.transcript = crate::config::Transcript::Off),
+            ("profile", |c| c.profile = String::from("staging")),
+            ("drafts", |c| c.drafts = !c.drafts),
+            ("future", |c| c.future = !c.future),
+            ("expired", |c| c.expired = !c.expired),
+            ("taxonomies", |c| { c.taxonomies.insert(String::from("people"), Default::default()); }),
+            ("feeds", |c| { c.feeds.insert(String::from("blog"), Default::default()); }),
+            ("refroots", |c| { c.refroots.insert(String::from("archive"), PathBuf::from("archive")); }),
+        ];
+        for (name, change) in changes {
+            let mut changed = config.clone();
+            change(&mut changed);
+            assert_ne!(base_key(&changed).unwrap(), base, "{} should change the key", name);
+        }
+        assert_eq!(base_key(&config).unwrap(), base);
+    }
+
+    #[test]
+    fn test_hash_parts() {
+        assert_eq!(hash_parts(&[b"ab", b"c"]), hash_parts(&[b"ab", b"c"]));
+        assert_ne!(hash_parts(&[b"ab", b"c"]), hash_parts(&[b"a", b"bc"]));
+    }
+}
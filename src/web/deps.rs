@@ -0,0 +1,329 @@
+// dependency graph for incremental rebuilds:
+// which output each source file is written to, which templates
+// (layouts, partials and generated ref partials) each page is rendered with,
+// and which sources it links to
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tracing::trace;
+use walkdir::WalkDir;
+
+use crate::{
+    config::Config,
+    util::*,
+    web::{
+        cache,
+        diagnostic::{Diagnostic, Diagnostics, Phase},
+        document::{layout_name, read_source, Document},
+        paginate,
+    },
+};
+
+// `{{> name}}`, `{{#> name}}` and `{{~> "name"}}`; `(` starts a dynamic partial name
+fn partial_regex() -> &'static Regex {
+    static PARTIAL: OnceLock<Regex> = OnceLock::new();
+    PARTIAL.get_or_init(|| {
+        Regex::new(r#"\{\{~?\s*#?\s*>\s*("[^"]+"|'[^']+'|\(|[^\s}~]+)"#).expect("partial regex")
+    })
+}
+
+// `pages` in any expression: site.pages, @root.site.pages,
+// or pages inside {{#with site}}
+fn pages_regex() -> &'static Regex {
+    static PAGES: OnceLock<Regex> = OnceLock::new();
+    PAGES.get_or_init(|| Regex::new(r"\{\{[^}]*\bpages\b").expect("pages regex"))
+}
+
+// stands for site.pages among the templates a page depends on,
+// so pages that list other pages render again when the list changes
+pub const SITE_PAGES: &str = "@site.pages";
+
+// templates used by some template text
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dependencies {
+    pub templates: BTreeSet<String>,
+    // a partial is chosen at render time: depends on every template
+    pub dynamic: bool,
+    // sources linked to when the page was last rendered, see cache::recording_links
+    pub links: BTreeSet<PathBuf>,
+}
+
+impl Dependencies {
+    pub fn from_template(text: &str) -> Dependencies {
+        let mut deps = Dependencies::default();
+        for capture in partial_regex().captures_iter(text) {
+            let name = &capture[1];
+            if name == "(" {
+                deps.dynamic = true;
+            } else {
+                deps.templates.insert(name.trim_matches(|c| c == '"' || c == '\'').to_string());
+            }
+        }
+        if pages_regex().is_match(text) {
+            deps.templates.insert(SITE_PAGES.to_string());
+        }
+        deps
+    }
+
+    // generated ref partials: ref/<name>.html or <namespace>/ref/<name>.html
+    pub fn refs(&self) -> Vec<String> {
+        self.templates.iter()
+            .filter(|name| {
+                let mut segments = name.split('/');
+                segments.next() == Some("ref") || segments.next() == Some("ref")
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Page {
+    outpath: PathBuf,
+    deps: Dependencies,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DepGraph {
+    // source path → output and templates it was rendered with
+    pages: BTreeMap<PathBuf, Page>,
+    // template name → templates it includes
+    templates: BTreeMap<String, Dependencies>,
+}
+
+impl DepGraph {
+    // every page in sourcedir and every template in the build template directory
+    // a page that can't be read is recorded in diagnostics and left out
+    pub fn scan(config: &Config, diagnostics: &mut Diagnostics) -> anyhow::Result<DepGraph> {
+        let mut graph = DepGraph::default();
+        graph.scan_templates(config)?;
+        let walker = WalkDir::new(&config.sourcedir)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| !e.is_hidden());
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                if let Err(e) = graph.record(config, entry.path()) {
+                    diagnostics.record(Diagnostic::from_error(entry.path(), Phase::FrontMatter, &e))?;
+                }
+            }
+        }
+        for (sourcepath, links) in cache::links(config) {
+            graph.linked(&sourcepath, links);
+        }
+        Ok(graph)
+    }
+
+    // template names as registered from the build template directory:
+    // relative path without .hbs, e.g. default, _audioplayback, archive/ref/quote.html
+    pub fn scan_templates(&mut self, config: &Config) -> anyhow::Result<()> {
+        let buildtemplatedir = config.buildtemplatedir();
+        self.templates.clear();
+        for entry in WalkDir::new(&buildtemplatedir).into_iter().filter_entry(|e| !e.is_hidden()) {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type().is_file() && path.extension() == Some(OsStr::new("hbs")) {
+                let name = template_name(path.strip_prefix(&buildtemplatedir)?);
+                let deps = Dependencies::from_template(&read_file_to_string(path)?);
+                self.templates.insert(name, deps);
+            }
+        }
+        Ok(())
+    }
+
+    // (re)compute what a source file depends on, keeping its links until it
+    // renders again; true if it is new or its permalink changed
+    pub fn record(&mut self, config: &Config, sourcepath: &Path) -> anyhow::Result<bool> {
+        let outpath = Document::from_path(sourcepath).output_path(config)?;
+        let mut deps = DepGraph::dependencies(sourcepath)?;
+        let moved = match self.pages.get(sourcepath) {
+            Some(before) => {
+                if before.outpath != outpath && before.outpath.is_file() {
+                    std::fs::remove_file(&before.outpath)?;
+                    paginate::remove_pages(&before.outpath, 2)?;
+                }
+                deps.links = before.deps.links.clone();
+                before.outpath != outpath
+            },
+            None => true,
+        };
+        self.pages.insert(sourcepath.to_path_buf(), Page { outpath, deps });
+        Ok(moved)
+    }
+
+    // the sources a page linked to as it rendered
+    pub fn linked(&mut self, sourcepath: &Path, links: BTreeSet<PathBuf>) {
+        if let Some(page) = self.pages.get_mut(sourcepath) {
+            page.deps.links = links;
+        }
+    }
+
+    // pages that link to any of the targets
+    pub fn pages_linking(&self, targets: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+        self.pages.iter()
+            .filter(|(_, page)| !page.deps.links.is_disjoint(targets))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    // the layout and partials a source is rendered with
+    pub fn dependencies(sourcepath: &Path) -> anyhow::Result<Dependencies> {
+        let document = Document::from_path(sourcepath);
+        let deps = match document.mime.subtype().as_str() {
+            "x-handlebars-template" => {
+                let (attr, content) = read_source(sourcepath)?;
+                let mut deps = Dependencies::from_template(&content);
+                deps.templates.insert(layout_name(&attr).to_string());
+                // a paginated collection is most likely site.pages, or built from it
+                if attr.contains_key("paginate") {
+                    deps.templates.insert(SITE_PAGES.to_string());
+                }
+                deps
+            },
+            "markdown" => {
+                let (attr, _) = read_source(sourcepath)?;
+                let mut deps = Dependencies::default();
+                deps.templates.insert(layout_name(&attr).to_string());
+                deps
+            },
+            _ => Dependencies::default(),
+        };
+        trace!("deps {}: {:?}", sourcepath.display(), deps);
+        Ok(deps)
+    }
+
+    // forget a deleted source, returning the output it was written to
+    pub fn remove(&mut self, sourcepath: &Path) -> Option<PathBuf> {
+        self.pages.remove(sourcepath).map(|page| page.outpath)
+    }
+
+    // sources inside a deleted directory
+    pub fn sources_in(&self, dir: &Path) -> Vec<PathBuf> {
+        self.pages.keys().filter(|path| path.starts_with(dir)).cloned().collect()
+    }
+
+    // pages rendered with any of the changed templates,
+    // directly or through partials that include them
+    pub fn pages_using(&self, changed: &BTreeSet<String>) -> Vec<PathBuf> {
+        if changed.is_empty() {
+            return Vec::new();
+        }
+        let affected = self.templates_using(changed);
+        self.pages.iter()
+            .filter(|(_, page)| {
+                page.deps.dynamic || page.deps.templates.iter().any(|t| affected.contains(t))
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    // whether a page with these dependencies lists site.pages,
+    // itself or through its layout and partials
+    pub fn uses_pages(&self, deps: &Dependencies) -> bool {
+        let affected = self.templates_using(&BTreeSet::from([SITE_PAGES.to_string()]));
+        deps.dynamic || deps.templates.iter().any(|t| affected.contains(t))
+    }
+
+    // the changed templates and every template that includes one of them
+    fn templates_using(&self, changed: &BTreeSet<String>) -> BTreeSet<String> {
+        let mut affected = changed.clone();
+        loop {
+            let before = affected.len();
+            for (name, deps) in &self.templates {
+                if !affected.contains(name)
+                    && (deps.dynamic || deps.templates.iter().any(|t| affected.contains(t))) {
+                    affected.insert(name.clone());
+                }
+            }
+            if affected.len() == before {
+                break;
+            }
+        }
+        affected
+    }
+}
+
+pub fn template_name(rel_path: &Path) -> String {
+    rel_path.with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> BTreeSet<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_dependencies_from_template() {
+        let deps = Dependencies::from_template(
+            "{{> _header}} {{#> layout/base}}x{{/layout/base}} {{~> \"ref/quote.html\" }} {{> archive/ref/a.html}}");
+        assert_eq!(deps.templates, names(&["_header", "layout/base", "ref/quote.html", "archive/ref/a.html"]));
+        assert!(!deps.dynamic);
+        assert!(Dependencies::from_template("{{> (lookup this 'partial') }}").dynamic);
+        assert!(Dependencies::from_template("{{ name }} {{#if x}}>{{/if}}").templates.is_empty());
+        assert_eq!(deps.refs(), vec!["archive/ref/a.html", "ref/quote.html"]);
+        assert!(Dependencies::from_template("{{#each site.pages}}{{ title }}{{/each}}").templates.contains(SITE_PAGES));
+        assert!(Dependencies::from_template("{{#with site}}{{#each pages}}{{ title }}{{/each}}{{/with}}").templates.contains(SITE_PAGES));
+        assert!(!Dependencies::from_template("<p>all pages</p>").templates.contains(SITE_PAGES));
+    }
+
+    #[test]
+    // a page with bad front matter is reported, the rest are still scanned
+    fn test_scan_skips_unreadable() {
+        let dir = std::env::temp_dir().join("altwebgen-deps-scan");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .build().unwrap();
+        std::fs::create_dir_all(config.buildtemplatedir()).unwrap();
+        std::fs::create_dir_all(&config.sourcedir).unwrap();
+        std::fs::write(config.sourcedir.join("a.md"), "# A").unwrap();
+        std::fs::write(config.sourcedir.join("b.md"), "---\npermalink: [\n---\n# B").unwrap();
+        let mut diagnostics = Diagnostics::new(false);
+        let graph = DepGraph::scan(&config, &mut diagnostics).unwrap();
+        assert!(graph.pages.contains_key(&config.sourcedir.join("a.md")));
+        assert!(!graph.pages.contains_key(&config.sourcedir.join("b.md")));
+        let failure = diagnostics.finish().unwrap_err().to_string();
+        assert!(failure.contains("b.md"), "{}", failure);
+    }
+
+    #[test]
+    fn test_template_name() {
+        assert_eq!(template_name(Path::new("default.hbs")), "default");
+        assert_eq!(template_name(Path::new("archive/ref/quote.html.hbs")), "archive/ref/quote.html");
+    }
+
+    #[test]
+    fn test_pages_using() {
+        let mut graph = DepGraph::default();
+        graph.templates.insert("default".into(), Dependencies::from_template("{{> _nav}}{{{body}}}"));
+        graph.templates.insert("_nav".into(), Dependencies::from_template("{{> _links}}"));
+        graph.templates.insert("_links".into(), Dependencies::default());
+        graph.templates.insert("words".into(), Dependencies::default());
+        let page = |templates: &[&str]| Page {
+            outpath: PathBuf::new(),
+            deps: Dependencies { templates: names(templates), ..Default::default() },
+        };
+        graph.pages.insert("a.md".into(), page(&["default"]));
+        graph.pages.insert("b.md".into(), page(&["words"]));
+        graph.pages.insert("c.md.hbs".into(), page(&["words", "ref/quote.html"]));
+
+        assert_eq!(graph.pages_using(&names(&["_links"])), vec![PathBuf::from("a.md")]);
+        assert_eq!(graph.pages_using(&names(&["ref/quote.html"])), vec![PathBuf::from("c.md.hbs")]);
+        assert_eq!(graph.pages_using(&names(&["words"])).len(), 2);
+        assert!(graph.pages_using(&BTreeSet::new()).is_empty());
+    }
+}
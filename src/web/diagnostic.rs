@@ -0,0 +1,203 @@
+// build failures, collected so one bad page doesn't stop the rest of the build:
+// each names the file, the line when the error carries one, and the build phase
+use handlebars::{RenderError, TemplateError};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::web::{document::content_line_offset, md::BrokenLinks};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    // generating ref partials and copying their audio
+    Ref,
+    // parsing yaml front matter
+    FrontMatter,
+    // rendering a page with its template and layout
+    Render,
+    // copying a file that isn't rendered
+    Copy,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Ref => "ref",
+            Phase::FrontMatter => "front matter",
+            Phase::Render => "render",
+            Phase::Copy => "copy",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub phase: Phase,
+    pub message: String,
+}
+
+impl Diagnostic {
+    // phase is where the error happened, refined by what the error is:
+    // yaml errors are front matter, and template errors in the page's own
+    // content give a line in the source file
+    pub fn from_error(path: &Path, phase: Phase, error: &anyhow::Error) -> Diagnostic {
+        let mut diagnostic = Diagnostic {
+            path: path.to_path_buf(),
+            line: None,
+            phase,
+            message: message(error),
+        };
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<serde_yaml::Error>() {
+                diagnostic.phase = Phase::FrontMatter;
+                // yaml starts on the line after the opening ---
+                diagnostic.line = e.location().map(|location| location.line() + 1);
+                break;
+            }
+            if let Some(e) = cause.downcast_ref::<RenderError>() {
+                if e.template_name.is_none() && e.line_no.is_some() {
+                    diagnostic.line = e.line_no.map(|line| line + content_line_offset(path));
+                    break;
+                }
+            }
+            if let Some(e) = cause.downcast_ref::<BrokenLinks>() {
+                diagnostic.line = Some(e.line + content_line_offset(path));
+                break;
+            }
+            // only the page's own content is compiled while rendering,
+            // layouts and partials are parsed when they are registered
+            if let Some(e) = cause.downcast_ref::<TemplateError>() {
+                diagnostic.line = e.pos().map(|(line, _)| line + content_line_offset(path));
+                break;
+            }
+        }
+        diagnostic
+    }
+}
+
+// the error and its causes, skipping causes already quoted by the error above
+fn message(error: &anyhow::Error) -> String {
+    let mut message = String::new();
+    for cause in error.chain() {
+        let text = cause.to_string();
+        if !message.contains(&text) {
+            if !message.is_empty() {
+                message.push_str(": ");
+            }
+            message.push_str(&text);
+        }
+    }
+    message
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}: {}", self.path.display(), line, self.phase, self.message),
+            None => write!(f, "{}: {}: {}", self.path.display(), self.phase, self.message),
+        }
+    }
+}
+
+// the error a build returns when any file failed
+#[derive(Debug, Default)]
+pub struct BuildFailure {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for BuildFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.diagnostics.len();
+        write!(f, "build failed with {} error{}", count, if count == 1 { "" } else { "s" })?;
+        for diagnostic in &self.diagnostics {
+            write!(f, "\n  {}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildFailure {}
+
+// failures so far in one build, or the first one with --fail-fast
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    fail_fast: bool,
+    failure: BuildFailure,
+}
+
+impl Diagnostics {
+    pub fn new(fail_fast: bool) -> Diagnostics {
+        Diagnostics { fail_fast, failure: BuildFailure::default() }
+    }
+
+    // keep going after a failure, or stop the build with --fail-fast
+    pub fn record(&mut self, diagnostic: Diagnostic) -> anyhow::Result<()> {
+        if self.fail_fast {
+            return Err(BuildFailure { diagnostics: vec![diagnostic] }.into());
+        }
+        self.failure.diagnostics.push(diagnostic);
+        Ok(())
+    }
+
+    // a step that reports its own failures, any other error stops the build
+    pub fn check(&mut self, result: anyhow::Result<()>) -> anyhow::Result<()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => match e.downcast::<BuildFailure>() {
+                Ok(failure) => {
+                    for diagnostic in failure.diagnostics {
+                        self.record(diagnostic)?;
+                    }
+                    Ok(())
+                },
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    // reported in source order, whichever step found them
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if self.failure.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            self.failure.diagnostics.sort_by(|a, b| a.path.cmp(&b.path));
+            Err(self.failure.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_front_matter_line() {
+        let yaml_error = crate::util::parse_yaml_attributes("title: a\nbad: [\n").unwrap_err();
+        let diagnostic = Diagnostic::from_error(Path::new("source/a.md"), Phase::Render, &yaml_error);
+        assert_eq!(diagnostic.phase, Phase::FrontMatter);
+        assert!(diagnostic.line.is_some());
+        assert!(diagnostic.to_string().starts_with("source/a.md:"), "{}", diagnostic);
+    }
+
+    #[test]
+    fn test_diagnostics_fail_fast() {
+        let diagnostic = Diagnostic {
+            path: PathBuf::from("source/a.md"),
+            line: Some(3),
+            phase: Phase::Render,
+            message: String::from("missing partial"),
+        };
+        let mut diagnostics = Diagnostics::new(false);
+        diagnostics.record(diagnostic.clone()).unwrap();
+        diagnostics.record(diagnostic.clone()).unwrap();
+        let err = diagnostics.finish().unwrap_err();
+        assert_eq!(err.downcast_ref::<BuildFailure>().unwrap().diagnostics.len(), 2);
+        assert!(err.to_string().contains("source/a.md:3: render: missing partial"), "{}", err);
+
+        let mut diagnostics = Diagnostics::new(true);
+        assert!(diagnostics.record(diagnostic).is_err());
+    }
+}
@@ -1,10 +1,13 @@
 use mime::Mime;
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tracing::{info, trace};
 use crate::config::{Config, Context};
-use crate::web::md;
+use crate::web::{md, paginate, permalink};
 use crate::util::*;
 
 #[derive(Debug, Clone)]
@@ -26,7 +29,19 @@ impl Document {
             }
         }
     }
+    // where this html source is rendered to, see permalink::apply
     pub fn outpath(&self, config: &Config) -> anyhow::Result<PathBuf> {
+        let path = self.source_outpath(config)?;
+        // a deleted source is looked up by the path it was written to
+        if !self.is_page(&path) || !self.path.is_file() {
+            return Ok(path);
+        }
+        let (attr, _) = read_source(&self.path)?;
+        permalink::apply(config, &self.path, path, &attr)
+    }
+
+    // the source path in outdir, with an html extension
+    fn source_outpath(&self, config: &Config) -> anyhow::Result<PathBuf> {
         use std::ffi::OsStr;
         let stem = config.outpath(&self.path)?;
         let path = match self.mime.subtype().as_str() {
@@ -44,6 +59,29 @@ impl Document {
         Ok(path)
     }
 
+    // markdown or a template rendered to html, with front matter
+    fn is_page(&self, outpath: &Path) -> bool {
+        match self.mime.subtype().as_str() {
+            "markdown" => true,
+            "x-handlebars-template" => outpath.extension().is_some_and(|ext| ext == "html"),
+            _ => false,
+        }
+    }
+
+    // rendered to html, everything else is copied as-is
+    pub fn is_html_source(&self) -> bool {
+        matches!(self.mime.subtype().as_str(), "x-handlebars-template" | "markdown" | "html")
+    }
+
+    // where render writes this document
+    pub fn output_path(&self, config: &Config) -> anyhow::Result<PathBuf> {
+        if self.is_html_source() {
+            self.outpath(config)
+        } else {
+            Ok(config.outpath(&self.path)?)
+        }
+    }
+
     pub fn html_generator(&self, context: &Context) -> anyhow::Result<Option<HtmlGenerator>> {
         match HtmlGenerator::from_document(context, self) {
             Err(e) => {
@@ -58,6 +96,94 @@ impl Document {
     }
 }
 
+// where each source is written, resolved once per build and passed through,
+// since a page's outpath depends on its front matter, see Document::outpath;
+// shared by the context and the url helper
+#[derive(Clone, Debug, Default)]
+pub struct Outpaths(Arc<BTreeMap<PathBuf, PathBuf>>);
+
+impl Outpaths {
+    pub fn insert(&mut self, source: PathBuf, outpath: PathBuf) {
+        Arc::make_mut(&mut self.0).insert(source, outpath);
+    }
+
+    pub fn remove(&mut self, source: &Path) {
+        if self.0.contains_key(source) {
+            Arc::make_mut(&mut self.0).remove(source);
+        }
+    }
+
+    // sources under a deleted directory
+    pub fn remove_under(&mut self, dir: &Path) {
+        if self.0.keys().any(|source| source.starts_with(dir)) {
+            Arc::make_mut(&mut self.0).retain(|source, _| !source.starts_with(dir));
+        }
+    }
+
+    // the same outpaths under another outdir
+    pub fn moved(&self, from: &Path, to: &Path) -> Outpaths {
+        let moved = self.0.iter()
+            .map(|(source, outpath)| {
+                let outpath = outpath.strip_prefix(from).map_or_else(|_| outpath.clone(), |rel_path| to.join(rel_path));
+                (source.clone(), outpath)
+            })
+            .collect();
+        Outpaths(Arc::new(moved))
+    }
+
+    // a source outside the build, e.g. a draft, is resolved on its own
+    pub fn get(&self, config: &Config, source: &Path) -> anyhow::Result<PathBuf> {
+        match self.0.get(source) {
+            Some(outpath) => Ok(outpath.clone()),
+            None => Document::from_path(source).output_path(config),
+        }
+    }
+}
+
+// templates' `site`: site attributes and site.pages, built once when the pages
+// are known; a render moves it into the page's data and back out after,
+// so each page doesn't copy every other page (see render)
+#[derive(Debug, Default)]
+pub struct SiteData {
+    value: Value,
+    // copies returned by finished renders, at most one per render thread
+    spare: Mutex<Vec<Value>>,
+}
+
+impl Clone for SiteData {
+    fn clone(&self) -> Self {
+        SiteData { value: self.value.clone(), spare: Mutex::default() }
+    }
+}
+
+impl SiteData {
+    pub fn new(config: &Config, pages: &[Attributes]) -> SiteData {
+        let mut site = config.site_attr.clone();
+        let pages = pages.iter().cloned().map(Value::Object).collect();
+        site.insert("pages".into(), Value::Array(pages));
+        SiteData { value: Value::Object(site), spare: Mutex::default() }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    // renders with a page's data and `site`
+    pub fn render<T>(&self, data: &Attributes, render: impl FnOnce(&handlebars::Context) -> T) -> T {
+        let site = self.spare.lock().unwrap().pop()
+            .unwrap_or_else(|| self.value.clone());
+        let mut data = data.clone();
+        data.insert("site".into(), site);
+        let mut hbs_data = handlebars::Context::from(Value::Object(data));
+        let result = render(&hbs_data);
+        if let Value::Object(mut data) = std::mem::take(hbs_data.data_mut()) {
+            if let Some(site) = data.remove("site") {
+                self.spare.lock().unwrap().push(site);
+            }
+        }
+        result
+    }
+}
 
 //-------------errors----------------
 #[derive(Debug, Clone, PartialEq)]
@@ -109,36 +235,85 @@ impl HtmlGenerator {
     }
 }
 //---------------
-use std::collections::HashMap;
 
 // private function
 //   reads markdown source file
-//   parses yaml front matter into Hashmap of (key, value) pairs
-//   returns Hashmap + rest of file
-fn read_source<P: AsRef<Path>>(sourcepath: P) -> anyhow::Result<(HashMap<String, String>, String)>
+//   parses yaml front matter into map of (key, value) pairs
+//   returns Attributes + rest of file
+pub(super) fn read_source<P: AsRef<Path>>(sourcepath: P) -> anyhow::Result<(Attributes, String)>
 {
     let source = read_file_to_string(sourcepath)?;
     use matter::matter;
     let (data, content) = match matter(&source) {
         None => {trace!("matter: None");
-            let data: HashMap<String, String> = HashMap::new();
-            (data, source)
+            (Attributes::new(), source)
         },
 
         Some((yaml_string, content)) => {
             trace!("matter:\n{:?}\n------", yaml_string);
-            let data:HashMap<String, String> = serde_yaml::from_str(&yaml_string)?;
-
-            //  let data: HashMap<&str, String> = HashMap::new();
+            let data = parse_yaml_attributes(&yaml_string)?;
             (data, content)
         }
     };
     Ok((data, content))
 }
 
+// lines before a source's content, i.e. its front matter,
+// so a line in the content can be reported as a line in the file
+pub(super) fn content_line_offset(sourcepath: &Path) -> usize {
+    let Ok(source) = read_file_to_string(sourcepath) else { return 0 };
+    match matter::matter(&source) {
+        Some((_, content)) if !content.is_empty() => source.rfind(content.as_str())
+            .map(|start| source[..start].matches('\n').count())
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
+
+// relative url from a page to the site root, e.g. "../" for a page in a sub directory
+fn path_to_root(config: &Config, outpath: &Path) -> String {
+    let depth = outpath.strip_prefix(&config.outdir)
+        .map(|rel_path| rel_path.components().count().saturating_sub(1))
+        .unwrap_or(0);
+    "../".repeat(depth)
+}
+
+// template data for a page: its own front matter and the page's own `url`,
+// rendered with site attributes under `site`, see SiteData
+pub(super) fn page_data(context: &Context, sourcepath: &Path, attr: Attributes) -> anyhow::Result<Attributes> {
+    let config = context.config;
+    // a source rendered on its own, outside sourcedir, has no place in the site;
+    // any other is where the build resolved it to be written, see Outpaths
+    let outpath = match sourcepath.starts_with(&config.sourcedir) {
+        true => Some(context.outpaths.get(config, sourcepath)?),
+        false => None,
+    };
+    output_data(context, outpath.as_deref(), attr)
+}
+
+// template data for a page written to outpath, with or without a source
+pub(super) fn output_data(context: &Context, outpath: Option<&Path>, attr: Attributes) -> anyhow::Result<Attributes> {
+    let config = context.config;
+    let mut data = attr;
+    data.insert("profile".into(), Value::String(config.profile.clone()));
+    if let Some(outpath) = outpath {
+        data.entry("path_to_root")
+            .or_insert_with(|| Value::String(path_to_root(config, outpath)));
+        let url = permalink::url(config, outpath)?;
+        data.entry("url").or_insert(Value::String(url));
+    }
+    Ok(data)
+}
+
+pub(super) fn layout_name(attr: &Attributes) -> &str {
+    attr.get("layout")
+        .and_then(Value::as_str)
+        .unwrap_or("default")
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct MarkdownData {
-    attr: HashMap<String, String>,
+    attr: Attributes,
 }
 
 trait GenerateHtml {
@@ -147,28 +322,22 @@ trait GenerateHtml {
 
 impl GenerateHtml for MarkdownData {
     fn render<W: Write>(&self, context: &Context, writer: &mut W) -> anyhow::Result<()> {
-        let layout_key = "layout".to_string();
-        let layout_name = if let Some(layout) = self.attr.get(&layout_key) {
-            layout.as_str()
-        } else {
-            "default"
-        };
+        let layout_name = layout_name(&self.attr);
         info!("MarkdownData::render with layout: {layout_name}");
-        context.hbs.render_to_write(layout_name, &self.attr, writer)?;
+        context.site.render(&self.attr, |data| context.hbs.render_with_context_to_write(layout_name, data, writer))?;
         Ok(())
     }
 }
 
 impl MarkdownData {
     fn from_path<P:AsRef<Path>>(context: &Context, path: P) -> anyhow::Result<Self> {
-        let (mut template_vars, content) = read_source(path)?;
-        let site_attr = context.config.site_attr.clone();
-        template_vars.extend(site_attr);
+        let (front_matter, content) = read_source(&path)?;
+        let mut template_vars = page_data(context, path.as_ref(), front_matter)?;
 
-        let html_body= md::str2html(&content)?;
+        let html_body= md::str2html_with_links(&content, context.config, &context.outpaths, path.as_ref())?;
         let body_string = String::from_utf8(html_body)?;
 
-        template_vars.insert("body".into(), body_string);
+        template_vars.insert("body".into(), Value::String(body_string));
 
         Ok(MarkdownData {
             attr: template_vars,
@@ -178,25 +347,25 @@ impl MarkdownData {
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct HandlebarsTemplate {
-    attr: HashMap<String, String>,
+    attr: Attributes,
     is_markdown: bool
 }
 
 impl GenerateHtml for HandlebarsTemplate {
     fn render<W: Write>(&self, context: &Context, writer: &mut W) -> anyhow::Result<()> {
-        let layout_key = "layout".to_string();
-        let layout_name = if let Some(layout) = self.attr.get(&layout_key) {
-            layout.as_str()
-        } else {
-            "default"
-        };
+        let layout_name = layout_name(&self.attr);
         trace!("HandlebarsTemplate::render with layout: {layout_name}");
-        context.hbs.render_to_write(layout_name, &self.attr, writer)?;
+        context.site.render(&self.attr, |data| context.hbs.render_with_context_to_write(layout_name, data, writer))?;
         Ok(())
     }
 }
 impl HandlebarsTemplate {
     fn from_path<P:AsRef<Path>>(context: &Context, path: P) -> anyhow::Result<Self> {
+        Self::from_page(context, path, None)
+    }
+
+    // one page of a paginated source, with its own url and paginator
+    pub(super) fn from_page<P:AsRef<Path>>(context: &Context, path: P, page: Option<&paginate::Page>) -> anyhow::Result<Self> {
         // check if target file has markdown, so we can render it as html below
         let mut is_markdown: bool = false;
         if let Some(root_file_name) = path.as_ref().file_stem() {
@@ -209,17 +378,21 @@ impl HandlebarsTemplate {
             };
         }
 
-        let (mut data, content) = read_source(path)?;
-        let site_attr = context.config.site_attr.clone();
-        data.extend(site_attr);
+        let (front_matter, content) = read_source(&path)?;
+        let mut data = page_data(context, path.as_ref(), front_matter)?;
+        if let Some(page) = page {
+            data.insert("url".into(), Value::String(page.url.clone()));
+            data.insert("path_to_root".into(), Value::String(path_to_root(context.config, &page.outpath)));
+            data.insert("paginator".into(), page.paginator.clone());
+        }
         let hbs = &context.hbs;
-        let mut rendered_body: String = hbs.render_template(&content, &data)?;
+        let mut rendered_body: String = context.site.render(&data, |data| hbs.render_template_with_context(&content, data))?;
 
         if is_markdown {
-            let html_bytes = md::str2html(&rendered_body)?;
+            let html_bytes = md::str2html_with_links(&rendered_body, context.config, &context.outpaths, path.as_ref())?;
             rendered_body = String::from_utf8(html_bytes)?;
         }
-        data.insert("body".into(), rendered_body);
+        data.insert("body".into(), Value::String(rendered_body));
         Ok(HandlebarsTemplate {
             attr: data,
             is_markdown
@@ -260,7 +433,10 @@ mod tests {
         let config = Config::default();
         let context = Context {
             config: &config,
-            hbs
+            hbs,
+            pages: Vec::new(),
+            site: SiteData::new(&config, &[]),
+            outpaths: Outpaths::default(),
         };
         let doc = Document::from_path("src/test/data/short-sentence.md");
         let maybe_html_source: Option<HtmlGenerator> = doc.html_generator(&context).unwrap();
@@ -283,7 +459,10 @@ mod tests {
         let config = Config::default();
         let context = Context {
             config: &config,
-            hbs
+            hbs,
+            pages: Vec::new(),
+            site: SiteData::new(&config, &[]),
+            outpaths: Outpaths::default(),
         };
         let doc = Document::from_path("src/test/data/var.md.hbs");
         let maybe_html_source: Option<HtmlGenerator> = doc.html_generator(&context).unwrap();
@@ -296,4 +475,72 @@ mod tests {
         }
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_html_gen_markdown_typed_front_matter() {
+        let default_tpl = "{{#each tags}}[{{this}}]{{/each}}{{#if draft}} draft{{/if}} by {{author.name}} on {{site.title}}";
+        let expected = "[audio][hypertext] draft by Ted Nelson on Hypertext Site";
+        let mut hbs = Handlebars::new();
+        hbs.register_template_string("default", default_tpl).unwrap();
+        let mut config = Config::default();
+        config.site_attr.insert("title".into(), Value::String("Hypertext Site".into()));
+        let context = Context {
+            config: &config,
+            hbs,
+            pages: Vec::new(),
+            site: SiteData::new(&config, &[]),
+            outpaths: Outpaths::default(),
+        };
+        let doc = Document::from_path("src/test/data/typed-front-matter.md");
+        let html_source = doc.html_generator(&context).unwrap().unwrap();
+        let mut write_buf: Vec<u8> = Vec::new();
+        html_source.render(&context, &mut write_buf).unwrap();
+        let output_string: String = String::from_utf8(write_buf).unwrap();
+        assert_eq!(expected, &output_string)
+    }
+
+    #[test]
+    // the page's url is where the build writes it, not resolved again
+    fn test_html_gen_markdown_url_from_outpaths() {
+        let mut hbs = Handlebars::new();
+        hbs.register_template_string("default", "{{ url }}").unwrap();
+        let mut config = Config::default();
+        config.sourcedir = PathBuf::from("src/test/data");
+        let mut outpaths = Outpaths::default();
+        outpaths.insert(PathBuf::from("src/test/data/short-sentence.md"), config.outdir.join("moved/index.html"));
+        let context = Context {
+            config: &config,
+            hbs,
+            pages: Vec::new(),
+            site: SiteData::new(&config, &[]),
+            outpaths,
+        };
+        let doc = Document::from_path("src/test/data/short-sentence.md");
+        let html_source = doc.html_generator(&context).unwrap().unwrap();
+        let mut write_buf: Vec<u8> = Vec::new();
+        html_source.render(&context, &mut write_buf).unwrap();
+        assert_eq!("/moved/", &String::from_utf8(write_buf).unwrap())
+    }
+
+    #[test]
+    fn test_html_gen_markdown_profile() {
+        let default_tpl = "{{#if (eq profile \"staging\")}}staging {{/if}}{{profile}}";
+        let mut hbs = Handlebars::new();
+        hbs.register_template_string("default", default_tpl).unwrap();
+        let mut config = Config::default();
+        config.profile = String::from("staging");
+        let context = Context {
+            config: &config,
+            hbs,
+            pages: Vec::new(),
+            site: SiteData::new(&config, &[]),
+            outpaths: Outpaths::default(),
+        };
+        let doc = Document::from_path("src/test/data/typed-front-matter.md");
+        let html_source = doc.html_generator(&context).unwrap().unwrap();
+        let mut write_buf: Vec<u8> = Vec::new();
+        html_source.render(&context, &mut write_buf).unwrap();
+        let output_string: String = String::from_utf8(write_buf).unwrap();
+        assert_eq!("staging staging", &output_string)
+    }
+}
@@ -0,0 +1,331 @@
+// feeds of the site's pages, configured as [feeds.<name>]:
+// <name>.xml in Atom and <name>.json in JSON Feed, newest first, and for a
+// podcast <name>.rss, an RSS feed of the pages that include a ref with audio,
+// each with the audio as enclosure and a podcast:transcript link;
+// urls are absolute, from the site attribute baseurl, e.g. https://example.com
+use anyhow::Context as _;
+use chrono::{DateTime, Local, SecondsFormat};
+use handlebars::html_escape;
+use kuchikiki::traits::TendrilSink;
+use serde_json::{json, Value};
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Config, Context, Feed},
+    util::{Attributes, PathExt},
+    web::{
+        deps::Dependencies,
+        document::{read_source, Document, Outpaths},
+        md::{ref_audio, RefAudio},
+        publish::parse_date,
+    },
+};
+
+const DEFAULT_LIMIT: usize = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    title: String,
+    // absolute
+    url: String,
+    summary: Option<String>,
+    // front matter date
+    published: Option<DateTime<Local>>,
+    // front matter updated or date, or when the source was last changed
+    updated: DateTime<Local>,
+    author: Option<String>,
+    enclosure: Option<Enclosure>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Enclosure {
+    url: String,
+    mime: String,
+    length: u64,
+    transcript: Option<String>,
+    // the transcript as copied into outdir
+    transcript_file: Option<PathBuf>,
+}
+
+// writes every feed, returns the files written, transcripts included
+pub fn write(context: &Context) -> anyhow::Result<Vec<PathBuf>> {
+    let config = context.config;
+    let mut written = Vec::new();
+    for (name, feed) in &config.feeds {
+        let baseurl = config.site_attr.get("baseurl").and_then(Value::as_str)
+            .with_context(|| format!("feed '{}' needs the site attribute baseurl, e.g. --set baseurl=https://example.com", name))?
+            .trim_end_matches('/');
+        let entries = entries(config, &context.outpaths, baseurl, feed, &context.pages)
+            .with_context(|| format!("feed '{}'", name))?;
+        let info = FeedInfo {
+            title: feed.title.clone()
+                .or_else(|| site_string(config, "title"))
+                .unwrap_or_else(|| name.clone()),
+            description: site_string(config, "description"),
+            author: config.site_attr.get("author").and_then(author_name),
+            home: format!("{}{}", baseurl, config.prefix),
+            feed_url: |extension: &str| format!("{}{}{}.{}", baseurl, config.prefix, name, extension),
+        };
+        let mut outputs = vec![
+            ("xml", atom(&info, &entries)),
+            ("json", json_feed(&info, &entries)?),
+        ];
+        if feed.podcast {
+            outputs.push(("rss", podcast(&info, &entries)));
+        }
+        for file in entries.iter().filter_map(|entry| entry.enclosure.as_ref()?.transcript_file.as_ref()) {
+            if !written.contains(file) {
+                written.push(file.clone());
+            }
+        }
+        for (extension, text) in outputs {
+            let path = config.outdir.join(name).with_extension(extension);
+            path.prepare_output()?;
+            std::fs::write(&path, text).with_context(|| format!("failed to write {}", path.display()))?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+fn site_string(config: &Config, key: &str) -> Option<String> {
+    config.site_attr.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+// `author: Ted Nelson` or `author: { name: Ted Nelson }`
+fn author_name(value: &Value) -> Option<String> {
+    value.as_str().or_else(|| value.get("name").and_then(Value::as_str)).map(str::to_string)
+}
+
+struct FeedInfo<F: Fn(&str) -> String> {
+    title: String,
+    description: Option<String>,
+    author: Option<String>,
+    home: String,
+    feed_url: F,
+}
+
+// pages in the feed's section, newest first, leaving out index pages
+// and any with `feed: false`
+fn entries(config: &Config, outpaths: &Outpaths, baseurl: &str, feed: &Feed, pages: &[Attributes]) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for page in pages {
+        let source = page.get("source").and_then(Value::as_str).unwrap_or_default();
+        let in_section = feed.section.iter()
+            .all(|section| source.starts_with(&format!("{}/", section.trim_matches('/'))));
+        let is_index = Path::new(source).file_name()
+            .is_some_and(|name| name.to_string_lossy().split('.').next() == Some("index"));
+        if !in_section || is_index || page.get("feed") == Some(&Value::Bool(false)) {
+            continue;
+        }
+        let sourcepath = config.sourcedir.join(source);
+        let url = page.get("url").and_then(Value::as_str).unwrap_or_default();
+        let date = |key: &str| page.get(key).and_then(Value::as_str)
+            .map(|text| parse_date(key, text))
+            .transpose()
+            .with_context(|| format!("{}: front matter {}", source, key));
+        let published = date("date")?;
+        let updated = match date("updated")?.or(published) {
+            Some(updated) => updated,
+            None => DateTime::from(std::fs::metadata(&sourcepath)?.modified()?),
+        };
+        entries.push(Entry {
+            title: page.get("title").and_then(Value::as_str).unwrap_or(source).to_string(),
+            url: format!("{}{}", baseurl, url),
+            summary: summary(config, outpaths, page, &sourcepath),
+            published,
+            updated,
+            author: page.get("author").and_then(author_name),
+            enclosure: enclosure(config, baseurl, &sourcepath)?,
+        });
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.published.unwrap_or(entry.updated)));
+    entries.truncate(feed.limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(entries)
+}
+
+// front matter summary or description, or the first paragraph of the page as rendered
+fn summary(config: &Config, outpaths: &Outpaths, page: &Attributes, sourcepath: &Path) -> Option<String> {
+    if let Some(summary) = page.get("summary").or(page.get("description")).and_then(Value::as_str) {
+        return Some(summary.to_string());
+    }
+    let outpath = outpaths.get(config, sourcepath).ok()?;
+    let html = std::fs::read_to_string(outpath).ok()?;
+    let paragraph = kuchikiki::parse_html().one(html).select_first("p").ok()?;
+    let text = paragraph.text_contents().split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+// the first ref with audio that a template page includes; its transcript
+// is published next to the audio so the feed can link to it
+fn enclosure(config: &Config, baseurl: &str, sourcepath: &Path) -> anyhow::Result<Option<Enclosure>> {
+    if Document::from_path(sourcepath).mime.subtype() != "x-handlebars-template" {
+        return Ok(None);
+    }
+    let (_, content) = read_source(sourcepath)?;
+    let found = Dependencies::from_template(&content).refs().iter()
+        .find_map(|partial| ref_audio(config, partial));
+    let Some(RefAudio { audio, transcript, media_dir }) = found else { return Ok(None) };
+    let media_url = |path: &Path| format!("{}{}{}/{}", baseurl, config.prefix, media_dir,
+        path.file_name().unwrap_or_default().to_string_lossy());
+    let transcript_file = match &transcript {
+        Some(transcript) => {
+            let dest = config.outdir.join(&media_dir).join(transcript.file_name().unwrap_or_default());
+            dest.prepare_output()?;
+            std::fs::copy(transcript, &dest)?;
+            Some(dest)
+        },
+        None => None,
+    };
+    Ok(Some(Enclosure {
+        url: media_url(&audio.path),
+        mime: audio.mime.essence_str().to_string(),
+        length: std::fs::metadata(&audio.path)?.len(),
+        transcript: transcript.as_deref().map(media_url),
+        transcript_file,
+    }))
+}
+
+fn rfc3339(date: &DateTime<Local>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// newest entry, for the feed as a whole
+fn updated(entries: &[Entry]) -> DateTime<Local> {
+    entries.iter().map(|entry| entry.updated).max().unwrap_or_else(Local::now)
+}
+
+fn atom<F: Fn(&str) -> String>(info: &FeedInfo<F>, entries: &[Entry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(xml, "  <title>{}</title>", html_escape(&info.title));
+    if let Some(description) = &info.description {
+        let _ = writeln!(xml, "  <subtitle>{}</subtitle>", html_escape(description));
+    }
+    let _ = writeln!(xml, "  <id>{}</id>", html_escape(&info.home));
+    let _ = writeln!(xml, "  <link href=\"{}\"/>", html_escape(&info.home));
+    let _ = writeln!(xml, "  <link rel=\"self\" href=\"{}\"/>", html_escape(&(info.feed_url)("xml")));
+    let _ = writeln!(xml, "  <updated>{}</updated>", rfc3339(&updated(entries)));
+    // atom needs an author for the feed or for every entry
+    let author = info.author.as_ref().unwrap_or(&info.title);
+    let _ = writeln!(xml, "  <author><name>{}</name></author>", html_escape(author));
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        let _ = writeln!(xml, "    <title>{}</title>", html_escape(&entry.title));
+        let _ = writeln!(xml, "    <id>{}</id>", html_escape(&entry.url));
+        let _ = writeln!(xml, "    <link href=\"{}\"/>", html_escape(&entry.url));
+        let _ = writeln!(xml, "    <updated>{}</updated>", rfc3339(&entry.updated));
+        if let Some(published) = &entry.published {
+            let _ = writeln!(xml, "    <published>{}</published>", rfc3339(published));
+        }
+        if let Some(author) = &entry.author {
+            let _ = writeln!(xml, "    <author><name>{}</name></author>", html_escape(author));
+        }
+        if let Some(summary) = &entry.summary {
+            let _ = writeln!(xml, "    <summary>{}</summary>", html_escape(summary));
+        }
+        if let Some(enclosure) = &entry.enclosure {
+            let _ = writeln!(xml, "    <link rel=\"enclosure\" href=\"{}\" type=\"{}\" length=\"{}\"/>",
+                html_escape(&enclosure.url), enclosure.mime, enclosure.length);
+        }
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn json_feed<F: Fn(&str) -> String>(info: &FeedInfo<F>, entries: &[Entry]) -> anyhow::Result<String> {
+    let items: Vec<Value> = entries.iter().map(|entry| {
+        let mut item = json!({
+            "id": entry.url,
+            "url": entry.url,
+            "title": entry.title,
+            "date_modified": rfc3339(&entry.updated),
+        });
+        if let Some(summary) = &entry.summary {
+            item["summary"] = json!(summary);
+            item["content_text"] = json!(summary);
+        }
+        if let Some(published) = &entry.published {
+            item["date_published"] = json!(rfc3339(published));
+        }
+        if let Some(author) = &entry.author {
+            item["authors"] = json!([{ "name": author }]);
+        }
+        if let Some(enclosure) = &entry.enclosure {
+            item["attachments"] = json!([{
+                "url": enclosure.url,
+                "mime_type": enclosure.mime,
+                "size_in_bytes": enclosure.length,
+            }]);
+        }
+        item
+    }).collect();
+    let mut feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": info.title,
+        "home_page_url": info.home,
+        "feed_url": (info.feed_url)("json"),
+        "items": items,
+    });
+    if let Some(description) = &info.description {
+        feed["description"] = json!(description);
+    }
+    if let Some(author) = &info.author {
+        feed["authors"] = json!([{ "name": author }]);
+    }
+    Ok(serde_json::to_string_pretty(&feed)? + "\n")
+}
+
+// only entries with audio, a podcast app has nothing to play for the rest
+fn podcast<F: Fn(&str) -> String>(info: &FeedInfo<F>, entries: &[Entry]) -> String {
+    let episodes: Vec<&Entry> = entries.iter().filter(|entry| entry.enclosure.is_some()).collect();
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" ",
+        "xmlns:podcast=\"https://podcastindex.org/namespace/1.0\">\n<channel>\n",
+    ));
+    let _ = writeln!(xml, "  <title>{}</title>", html_escape(&info.title));
+    let _ = writeln!(xml, "  <link>{}</link>", html_escape(&info.home));
+    let _ = writeln!(xml, "  <description>{}</description>",
+        html_escape(info.description.as_ref().unwrap_or(&info.title)));
+    let _ = writeln!(xml, "  <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>",
+        html_escape(&(info.feed_url)("rss")));
+    let updated = episodes.iter().map(|entry| entry.updated).max().unwrap_or_else(Local::now);
+    let _ = writeln!(xml, "  <lastBuildDate>{}</lastBuildDate>", updated.to_rfc2822());
+    for entry in episodes {
+        xml.push_str("  <item>\n");
+        let _ = writeln!(xml, "    <title>{}</title>", html_escape(&entry.title));
+        let _ = writeln!(xml, "    <link>{}</link>", html_escape(&entry.url));
+        let _ = writeln!(xml, "    <guid isPermaLink=\"true\">{}</guid>", html_escape(&entry.url));
+        let _ = writeln!(xml, "    <pubDate>{}</pubDate>", entry.published.unwrap_or(entry.updated).to_rfc2822());
+        if let Some(summary) = &entry.summary {
+            let _ = writeln!(xml, "    <description>{}</description>", html_escape(summary));
+        }
+        if let Some(enclosure) = &entry.enclosure {
+            let _ = writeln!(xml, "    <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>",
+                html_escape(&enclosure.url), enclosure.length, enclosure.mime);
+            if let Some(transcript) = &enclosure.transcript {
+                let _ = writeln!(xml, "    <podcast:transcript url=\"{}\" type=\"application/json\"/>",
+                    html_escape(transcript));
+            }
+        }
+        xml.push_str("  </item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_author_name() {
+        assert_eq!(author_name(&json!("Ted Nelson")), Some(String::from("Ted Nelson")));
+        assert_eq!(author_name(&json!({ "name": "Ada Lovelace", "url": "x" })), Some(String::from("Ada Lovelace")));
+        assert_eq!(author_name(&json!(["Ada"])), None);
+    }
+}
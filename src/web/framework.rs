@@ -49,7 +49,10 @@ mod tests {
         hbs.register_template_string("default", tmpl).unwrap();
         Context {
             config: config,
-            hbs
+            hbs,
+            pages: Vec::new(),
+            site: crate::web::SiteData::new(config, &[]),
+            outpaths: Default::default(),
         }
     }
 
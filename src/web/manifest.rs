@@ -0,0 +1,257 @@
+// record of what a build produced, written to builddir/manifest.json:
+// every file in outdir with its source, how it was generated, size,
+// content hash and render time, so deploys can upload only what changed
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    config::{ref_media_dir, Config},
+    web::{
+        deps::Dependencies,
+        document::{layout_name, read_source, Document, Outpaths},
+        paginate,
+        publish::Unpublished,
+        taxonomy::Listing,
+    },
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+// written by a build, replaces the manifest once its outputs are in outdir
+const PENDING_FILE: &str = "manifest.json.pending";
+
+// how an output was made, see document::HtmlGenerator
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Generator {
+    Markdown,
+    Template,
+    Html,
+    // a taxonomy's listing pages, see web::taxonomy
+    Listing,
+    // Atom, JSON and podcast feeds and the transcripts they link, see web::feed
+    Feed,
+    // sitemap.xml and robots.txt, see web::sitemap
+    Sitemap,
+    // copied as is: files in sourcedir that aren't pages, template assets, ref audio
+    Asset,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Output {
+    // relative to outdir
+    pub path: PathBuf,
+    // none for files from the built-in theme
+    pub source: Option<PathBuf>,
+    pub generator: Generator,
+    pub layout: Option<String>,
+    // ref partials the page includes, e.g. ref/quote.html
+    pub refs: Vec<String>,
+    pub size: u64,
+    pub sha256: String,
+    // none when the page wasn't rendered by any build that had the same output
+    pub render_ms: Option<f64>,
+}
+
+// a page this build didn't render, see publish::unpublished
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Skipped {
+    pub source: PathBuf,
+    #[serde(flatten)]
+    pub reason: Unpublished,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub outputs: Vec<Output>,
+    #[serde(default)]
+    pub skipped: Vec<Skipped>,
+}
+
+impl Manifest {
+    // every source file, with render times for the ones rendered by this build;
+    // pages skipped by the cache keep the time from the build that rendered them
+    pub fn for_sources(config: &Config, outpaths: &Outpaths, files: &[PathBuf], render_times: &BTreeMap<PathBuf, Duration>)
+        -> anyhow::Result<Manifest> {
+        let previous: BTreeMap<PathBuf, Output> = Manifest::load(config).unwrap_or_default().outputs.into_iter()
+            .map(|output| (output.path.clone(), output))
+            .collect();
+        let mut outputs = Vec::new();
+        for source in files {
+            let document = Document::from_path(source);
+            let outpath = outpaths.get(config, source)?;
+            let (generator, layout, refs) = describe(&document)?;
+            let mut output = Output {
+                path: outpath.strip_prefix(&config.outdir)?.to_path_buf(),
+                source: Some(source.clone()),
+                generator,
+                layout,
+                refs,
+                size: 0,
+                sha256: String::new(),
+                render_ms: render_times.get(source).map(|time| time.as_secs_f64() * 1000.0),
+            };
+            output.measure(&outpath)?;
+            if output.render_ms.is_none() {
+                output.render_ms = previous.get(&output.path)
+                    .filter(|before| before.sha256 == output.sha256)
+                    .and_then(|before| before.render_ms);
+            }
+            // the rest of a paginated source, rendered along with the first page
+            for page in paginate::extra_pages(&outpath) {
+                let mut extra = Output {
+                    path: page.strip_prefix(&config.outdir)?.to_path_buf(),
+                    render_ms: None,
+                    ..output.clone()
+                };
+                extra.measure(&page)?;
+                outputs.push(extra);
+            }
+            outputs.push(output);
+        }
+        Ok(Manifest { outputs, skipped: Vec::new() })
+    }
+
+    // pages generated for taxonomies, they have no source
+    pub fn add_listings(&mut self, config: &Config, listings: &[(Listing, Duration)]) -> anyhow::Result<()> {
+        for (listing, time) in listings {
+            let mut output = Output {
+                path: listing.outpath.strip_prefix(&config.outdir)?.to_path_buf(),
+                source: None,
+                generator: Generator::Listing,
+                layout: Some(listing.layout.clone()),
+                refs: Vec::new(),
+                size: 0,
+                sha256: String::new(),
+                render_ms: Some(time.as_secs_f64() * 1000.0),
+            };
+            output.measure(&listing.outpath)?;
+            self.outputs.push(output);
+        }
+        Ok(())
+    }
+
+    // files written from the whole site, like feeds and the sitemap
+    pub fn add_generated(&mut self, config: &Config, generator: Generator, files: &[PathBuf]) -> anyhow::Result<()> {
+        for file in files {
+            let mut output = Output {
+                path: file.strip_prefix(&config.outdir)?.to_path_buf(),
+                source: None,
+                generator,
+                layout: None,
+                refs: Vec::new(),
+                size: 0,
+                sha256: String::new(),
+                render_ms: None,
+            };
+            output.measure(file)?;
+            self.outputs.push(output);
+        }
+        Ok(())
+    }
+
+    // files in outdir that no source was rendered to: template assets and ref audio
+    pub fn add_assets(&mut self, config: &Config) -> anyhow::Result<()> {
+        let listed: BTreeSet<PathBuf> = self.outputs.iter().map(|output| output.path.clone()).collect();
+        for entry in WalkDir::new(&config.outdir).sort_by_file_name() {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path().strip_prefix(&config.outdir)?;
+            if listed.contains(path) {
+                continue;
+            }
+            let mut output = Output {
+                path: path.to_path_buf(),
+                source: asset_source(config, path),
+                generator: Generator::Asset,
+                layout: None,
+                refs: Vec::new(),
+                size: 0,
+                sha256: String::new(),
+                render_ms: None,
+            };
+            output.measure(entry.path())?;
+            self.outputs.push(output);
+        }
+        self.outputs.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(())
+    }
+
+    pub fn load(config: &Config) -> anyhow::Result<Manifest> {
+        let path = config.builddir.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(Manifest::default());
+        }
+        let text = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("failed to read build manifest {}", path.display()))
+    }
+
+    // see commit: only used once the build has succeeded
+    pub fn save(&self, config: &Config) -> anyhow::Result<()> {
+        let path = config.builddir.join(PENDING_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write build manifest {}", path.display()))
+    }
+}
+
+impl Output {
+    fn measure(&mut self, path: &Path) -> anyhow::Result<()> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read output {}", path.display()))?;
+        self.size = bytes.len() as u64;
+        self.sha256 = format!("{:x}", Sha256::digest(&bytes));
+        Ok(())
+    }
+}
+
+// after a build's outputs replaced outdir, its manifest replaces the previous one
+pub fn commit(config: &Config) -> anyhow::Result<()> {
+    let pending = config.builddir.join(PENDING_FILE);
+    if pending.is_file() {
+        std::fs::rename(&pending, config.builddir.join(MANIFEST_FILE))?;
+    }
+    Ok(())
+}
+
+// generator, layout and refs, from the source rather than the render,
+// so pages skipped by the cache are described the same way
+fn describe(document: &Document) -> anyhow::Result<(Generator, Option<String>, Vec<String>)> {
+    Ok(match document.mime.subtype().as_str() {
+        "x-handlebars-template" => {
+            let (attr, content) = read_source(&document.path)?;
+            let refs = Dependencies::from_template(&content).refs();
+            (Generator::Template, Some(layout_name(&attr).to_string()), refs)
+        },
+        "markdown" => {
+            let (attr, _) = read_source(&document.path)?;
+            (Generator::Markdown, Some(layout_name(&attr).to_string()), Vec::new())
+        },
+        "html" => (Generator::Html, None, Vec::new()),
+        _ => (Generator::Asset, None, Vec::new()),
+    })
+}
+
+// the template layer an asset came from, or the ref its audio was copied from
+fn asset_source(config: &Config, path: &Path) -> Option<PathBuf> {
+    if config.buildtemplatedir().join(path).is_file() {
+        return config.templatedirs.iter().rev()
+            .map(|layer| layer.join(path))
+            .find(|source| source.is_file());
+    }
+    let file_name = path.file_name()?;
+    config.ref_roots().into_iter()
+        .find(|(namespace, _)| path.parent() == Some(Path::new(&ref_media_dir(*namespace))))
+        .and_then(|(_, refdir)| WalkDir::new(refdir).into_iter()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name() == file_name)
+            .map(|entry| entry.into_path()))
+}
@@ -1,11 +1,14 @@
 use pulldown_cmark as cmark;
-use cmark::Event;
-use crate::web::read_file_to_string;
-use std::path::Path;
+use cmark::{Event, Tag};
+use crate::config::Config;
+use crate::web::{cache, document::Outpaths, permalink, read_file_to_string};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 use crate::web::words::*;
 
 mod ref_markdown;
 pub use ref_markdown::Ref as Ref;
+pub use ref_markdown::{ref_audio, RefAudio};
 
 pub fn file2html<P: AsRef<Path>>(sourcepath: P) -> anyhow::Result<Vec<u8>> {
     let source = read_file_to_string(sourcepath)?;
@@ -21,6 +24,108 @@ pub fn str2html(source: &str) -> anyhow::Result<Vec<u8>> {
     Ok(html_body)
 }
 
+// links to other sources are written as their output url, with prefix:
+// [Memex](memex.md#trails) → /memex.html#trails
+pub const SOURCE_EXTENSIONS: [&str; 3] = [".md", ".md.hbs", ".html.hbs"];
+
+// links from a page to source files that don't exist
+#[derive(Debug)]
+pub struct BrokenLinks {
+    // line of the first one, in the markdown
+    pub line: usize,
+    pub targets: Vec<String>,
+}
+
+impl fmt::Display for BrokenLinks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "link to missing source: {}", self.targets.join(", "))
+    }
+}
+
+impl std::error::Error for BrokenLinks {}
+
+// markdown of the page at sourcepath, links resolved relative to it
+pub fn str2html_with_links(source: &str, config: &Config, outpaths: &Outpaths, sourcepath: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut html_body: Vec<u8> = Vec::new();
+    let mut broken: Option<BrokenLinks> = None;
+    let mut events = Vec::new();
+    for (event, range) in cmark::Parser::new(source).into_offset_iter() {
+        let event = match event {
+            Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                let dest_url = match source_link(config, outpaths, sourcepath, &dest_url)? {
+                    SourceLink::Url(url) => url.into(),
+                    SourceLink::Other => dest_url,
+                    SourceLink::Missing => {
+                        let line = source[..range.start].matches('\n').count() + 1;
+                        broken.get_or_insert(BrokenLinks { line, targets: Vec::new() })
+                            .targets.push(dest_url.to_string());
+                        dest_url
+                    },
+                };
+                Event::Start(Tag::Link { link_type, dest_url, title, id })
+            },
+            _ => event,
+        };
+        events.push(event);
+    }
+    if let Some(broken) = broken {
+        return Err(broken.into());
+    }
+    cmark::html::write_html(&mut html_body, events.into_iter())?;
+    Ok(html_body)
+}
+
+enum SourceLink {
+    // output url of a source in sourcedir
+    Url(String),
+    Missing,
+    // a url, an asset or anything outside sourcedir
+    Other,
+}
+
+fn source_link(config: &Config, outpaths: &Outpaths, sourcepath: &Path, dest: &str) -> anyhow::Result<SourceLink> {
+    let (path, anchor) = match dest.find(['#', '?']) {
+        Some(i) => dest.split_at(i),
+        None => (dest, ""),
+    };
+    let is_url = path.split('/').next().is_some_and(|first| first.contains(':'));
+    if is_url || !SOURCE_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
+        return Ok(SourceLink::Other);
+    }
+    let target = match path.strip_prefix('/') {
+        Some(rel_path) => config.sourcedir.join(rel_path),
+        None => sourcepath.parent().unwrap_or(Path::new("")).join(path),
+    };
+    let Ok(rel_path) = normalize(&target).strip_prefix(normalize(&config.sourcedir)).map(Path::to_path_buf) else {
+        return Ok(SourceLink::Other);
+    };
+    let target = config.sourcedir.join(rel_path);
+    // a missing one too, so the page renders again once it's there
+    cache::record_link(&target);
+    if !target.is_file() {
+        return Ok(SourceLink::Missing);
+    }
+    let outpath = outpaths.get(config, &target)?;
+    Ok(SourceLink::Url(permalink::url(config, &outpath)? + anchor))
+}
+
+// a/b/../c → a/c, without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
 pub fn file2html_with_timing<P: AsRef<Path>>(md_path: P, transcript_path: P) -> anyhow::Result<Vec<u8>> {
     let md_text = read_file_to_string(md_path)?;
     let file = std::fs::File::open(transcript_path)?;
@@ -69,6 +174,38 @@ mod tests {
         assert_eq!("<p>hello world</p>\n", result_string);
     }
 
+    #[test]
+    fn str2html_with_links_to_sources() {
+        let dir = std::env::temp_dir().join("altwebgen-md-links");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source/blog")).unwrap();
+        std::fs::write(dir.join("source/index.md"), "# Home").unwrap();
+        std::fs::write(dir.join("source/blog/post.md.hbs"), "---\npermalink: /post/\n---\n# Post").unwrap();
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .prefix("/site/")
+            .build().unwrap();
+        let page = dir.join("source/blog/other.md");
+        let html = str2html_with_links(
+            "[home](../index.md) [post](post.md.hbs#end) [abs](/blog/post.md.hbs) [web](https://example.com/a.md) [css](style.css)",
+            &config, &Outpaths::default(), &page).unwrap();
+        assert_eq!(String::from_utf8(html).unwrap(), "<p><a href=\"/site/\">home</a> \
+            <a href=\"/site/post/#end\">post</a> <a href=\"/site/post/\">abs</a> \
+            <a href=\"https://example.com/a.md\">web</a> <a href=\"style.css\">css</a></p>\n");
+
+        // the build resolved where the target is written, it isn't read again
+        let mut outpaths = Outpaths::default();
+        outpaths.insert(dir.join("source/index.md"), dir.join(".dist/home/index.html"));
+        let html = str2html_with_links("[home](../index.md)", &config, &outpaths, &page).unwrap();
+        assert_eq!(String::from_utf8(html).unwrap(), "<p><a href=\"/site/home/\">home</a></p>\n");
+
+        let err = str2html_with_links("fine\n\n[a](missing.md) [b](gone.md)", &config, &Outpaths::default(), &page).unwrap_err();
+        let broken = err.downcast_ref::<BrokenLinks>().unwrap();
+        assert_eq!(broken.line, 3);
+        assert_eq!(broken.targets, vec!["missing.md", "gone.md"]);
+    }
+
     #[test]
     fn str2html_with_timing_phrase() {
         let timings = vec![
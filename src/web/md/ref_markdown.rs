@@ -1,5 +1,6 @@
 use mime::Mime;
 use new_mime_guess as mime_guess;
+use rayon::prelude::*;
 use std::{
     io::Write,
     path::{Path, PathBuf},
@@ -7,16 +8,59 @@ use std::{
 use tracing::{info, trace};
 use walkdir::WalkDir;
 
-use crate::{config::{Config, Transcript}, web::{self, md}};
+use crate::{
+    config::{ref_media_dir, ref_partial_dir, Config, Transcript},
+    util::PathExt,
+    web::{self, cache::RefCache, diagnostic::{BuildFailure, Diagnostic, Phase}, md},
+};
 
 #[derive(Debug, Clone)]
-struct AudioFile {
+pub struct AudioFile {
     pub path: PathBuf,
     pub mime: Mime,
 }
+
+// a ref's audio as published, for feeds
+#[derive(Debug, Clone)]
+pub struct RefAudio {
+    pub audio: AudioFile,
+    // whisperx json kept next to the audio
+    pub transcript: Option<PathBuf>,
+    // where the audio is copied, relative to outdir, see ref_media_dir
+    pub media_dir: String,
+}
+
+// the audio of a generated ref partial, e.g. ref/quote.html or archive/ref/quote.html,
+// found like process_matching does: a file with the same stem in the ref directory
+pub fn ref_audio(config: &Config, partial: &str) -> Option<RefAudio> {
+    let (namespace, stem) = match partial.split_once("/ref/") {
+        Some((namespace, stem)) => (Some(namespace), stem),
+        None => (None, partial.strip_prefix("ref/")?),
+    };
+    let (namespace, refdir) = config.ref_roots().into_iter().find(|(ns, _)| *ns == namespace)?;
+    let stem = refdir.join(stem).with_extension("").with_extension("");
+    let mut audio = None;
+    let mut transcript = None;
+    let walker = WalkDir::new(stem.parent()?).max_depth(1).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+    for entry in walker.into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.with_extension("").with_extension("") != stem || !path.is_file() {
+            continue;
+        }
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        match (mime.type_(), mime.subtype()) {
+            (mime::AUDIO, _) => audio = Some(AudioFile { path: path.to_path_buf(), mime }),
+            (mime::APPLICATION, mime::JSON) => transcript = Some(path.to_path_buf()),
+            _ => {},
+        }
+    }
+    Some(RefAudio { audio: audio?, transcript, media_dir: ref_media_dir(namespace) })
+}
 #[derive(Debug, Clone)]
 pub struct Ref<'a> {
     config: &'a Config,
+    // ref root namespace, None for the project's own refdir
+    namespace: Option<&'a str>,
     md: Option<PathBuf>,
     audio: Option<AudioFile>,
     transcript: Option<PathBuf>,
@@ -30,14 +74,21 @@ fn audio_tag(file_name: &str, audio_mime: &str, url:&str) -> String {
 }
 
 impl<'r> Ref<'r> {
-    pub fn new(config: &'r Config) -> Self {
+    pub fn new(config: &'r Config, namespace: Option<&'r str>) -> Self {
         Ref {
             config,
+            namespace,
             md: None,
             audio: None,
             transcript: None,
         }
     }
+    // the file a failure is reported against
+    fn source_path(&self) -> Option<&Path> {
+        self.md.as_deref()
+            .or(self.audio.as_ref().map(|audio| audio.path.as_path()))
+            .or(self.transcript.as_deref())
+    }
     fn write_html<W: Write>(&self, mut writer: W) -> anyhow::Result<()> {
         trace!("write_html for ref: {:?}", self);
         writer.write("<div class='ref'>\n".as_bytes())?;
@@ -45,7 +96,7 @@ impl<'r> Ref<'r> {
             writer.write("<div id='audiotext'>\n".as_bytes())?;
             trace!("write_html audio file_name: {:?}", audio.path.file_name());
             let file_name: &str = audio.path.file_name().unwrap().try_into()?;
-            let url = format!("{}media/{}", self.config.prefix, file_name);
+            let url = format!("{}{}/{}", self.config.prefix, ref_media_dir(self.namespace), file_name);
             let audio_html = audio_tag(file_name.into(), &audio.mime.to_string(), &url.to_string());
             writer.write(&audio_html.as_bytes())?;
         }
@@ -67,14 +118,48 @@ impl<'r> Ref<'r> {
 
         Ok(())
     }
+    // the generated partial, relative to dest_dir, for a ref with markdown
+    fn partial(&self, source_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+        match &self.md {
+            Some(md) => Ok(Some(md.strip_prefix(source_dir)?.with_extension("html.hbs"))),
+            None => Ok(None),
+        }
+    }
+    fn cache_key(&self) -> anyhow::Result<String> {
+        let audio = self.audio.as_ref().map(|audio| audio.path.as_path());
+        RefCache::key(self.config, self.namespace, &[self.md.as_deref(), audio, self.transcript.as_deref()])
+    }
+    // the published copy of the audio, see write_to_dest
+    fn audio_dest(&self) -> Option<PathBuf> {
+        let audio = self.audio.as_ref()?;
+        Some(self.config.outdir.join(ref_media_dir(self.namespace)).join(audio.path.file_name()?))
+    }
+    // a ref made from the same files and settings as its partial is kept,
+    // the rest are written; returns the key the partial was made with
+    fn write_if_changed(&mut self, source_dir: &Path, dest_dir: &Path, cache: &RefCache) -> anyhow::Result<Option<String>> {
+        let Some(partial) = self.partial(source_dir)? else {
+            self.write_to_dest(source_dir, dest_dir)?;
+            return Ok(None);
+        };
+        let key = self.cache_key()?;
+        let published = self.audio_dest().into_iter().all(|dest| dest.is_file());
+        if cache.is_fresh(&ref_partial_dir(self.namespace).join(&partial), &key)
+            && dest_dir.join(&partial).is_file() && published {
+            trace!("ref unchanged: {}", partial.display());
+            return Ok(Some(key));
+        }
+        self.write_to_dest(source_dir, dest_dir)?;
+        // a transcript generated while writing is part of the key from now on
+        Ok(Some(self.cache_key()?))
+    }
     pub fn write_to_dest(&mut self, source_dir: &Path, dest_dir: &Path) -> anyhow::Result<()> {
         trace!("write_to_dest Ref: {:?}", self);
         trace!("write_to_dest source_dir: {}, dest_dir: {}", source_dir.display(), dest_dir.display());
         if let Some(audio) = &self.audio {
             let source_path = &audio.path;
-            let outdir = &self.config.outdir;
-            let dest_path = outdir.join("media").join(source_path.file_name().unwrap());
+            let dest_path = self.audio_dest().unwrap();
             trace!("copy from {:?} to {:?}", &source_path, &dest_path);
+            dest_path.prepare_output()?;
             std::fs::copy(source_path, dest_path)?;
 
             if self.transcript == None && self.config.transcript == Transcript::On {
@@ -92,18 +177,57 @@ impl<'r> Ref<'r> {
             let mut writer = std::fs::File::options()
                 .create(true)
                 .write(true)
+                .truncate(true)
                 .open(writepath)?;
             self.write_html(&mut writer)?;
         }
         Ok(())
     }
 
-    pub fn process_markdown<P: AsRef<Path>>(
-        config: &Config,
+    pub fn process_markdown<'c, P: AsRef<Path>>(
+        config: &'c Config,
         source_dir: P,
+        namespace: Option<&'c str>,
+        dest_dir: &Path,
+    ) -> anyhow::Result<()> {
+        Ref::process_matching(config, source_dir.as_ref(), namespace, dest_dir, true, |_| true)
+    }
+
+    // regenerate the one ref a changed file belongs to (same stem),
+    // removing generated files for a source that was deleted
+    pub fn process_changed<'c>(
+        config: &'c Config,
+        source_dir: &Path,
+        namespace: Option<&'c str>,
         dest_dir: &Path,
+        changed: &Path,
+    ) -> anyhow::Result<()> {
+        if !changed.exists() {
+            let mime = mime_guess::from_path(changed).first_or_octet_stream();
+            let generated = if mime.type_() == mime::AUDIO {
+                config.outdir.join(ref_media_dir(namespace)).join(changed.file_name().unwrap_or_default())
+            } else {
+                dest_dir.join(changed.strip_prefix(source_dir)?).with_extension("html.hbs")
+            };
+            if generated.is_file() {
+                info!("removing {}, source deleted", generated.display());
+                std::fs::remove_file(&generated)?;
+            }
+        }
+        let stem = changed.with_extension("").with_extension("");
+        Ref::process_matching(config, source_dir, namespace, dest_dir, false,
+            |path| path.with_extension("").with_extension("") == stem)
+    }
+
+    // with all, partials of refs that are gone are removed
+    fn process_matching<'c, F: Fn(&Path) -> bool>(
+        config: &'c Config,
+        src_dir_path: &Path,
+        namespace: Option<&'c str>,
+        dest_dir: &Path,
+        all: bool,
+        include: F,
     ) -> anyhow::Result<()> {
-        let src_dir_path = source_dir.as_ref();
         if !src_dir_path.exists() {
             info!(
                 "skipping process_ref_markdown, no ref source directory: '{}'",
@@ -116,22 +240,23 @@ impl<'r> Ref<'r> {
             src_dir_path.display(),
             dest_dir.display()
         );
-        // maybe first create a map of stem => Vec[file types]
+        // group files by stem, then write each ref on the worker pool
+        let mut refs = Vec::new();
         let mut prev_stem = None;
-        let mut current_ref = Ref::new(&config);
+        let mut current_ref = Ref::new(config, namespace);
         for e in WalkDir::new(src_dir_path).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
             let entry = e?;
             let path: &Path = entry.path();
-            if std::fs::metadata(path)?.is_file() {
+            if std::fs::metadata(path)?.is_file() && include(path) {
                 let path_stem = path.with_extension("").with_extension("");
                 trace!("prev_stem: {:?}", prev_stem);
                 trace!("path_stem: {}", path_stem.display());
                 let mime = mime_guess::from_path(path).first_or_octet_stream();
                 if prev_stem != Some(path_stem.clone()) {
                     if prev_stem.is_some() {
-                        current_ref.write_to_dest(src_dir_path, &dest_dir)?;
+                        refs.push(current_ref);
                     }
-                    current_ref = Ref::new(config);
+                    current_ref = Ref::new(config, namespace);
                     prev_stem = Some(path_stem);
                 }
                 match (mime.type_(), mime.subtype()) {
@@ -160,9 +285,47 @@ impl<'r> Ref<'r> {
                 }
             }
         }
-        current_ref.write_to_dest(src_dir_path, &dest_dir)?;
+        refs.push(current_ref);
 
-        Ok(())
+        let mut cache = RefCache::load(config)?;
+        let results: Vec<anyhow::Result<Option<String>>> = refs.par_iter_mut()
+            .map(|reference| reference.write_if_changed(src_dir_path, dest_dir, &cache))
+            .collect();
+        let mut diagnostics = Vec::new();
+        let mut generated = Vec::new();
+        for (reference, result) in refs.iter().zip(results) {
+            let cached = reference.partial(src_dir_path)?.map(|partial| ref_partial_dir(namespace).join(partial));
+            match (result, cached) {
+                (Ok(Some(key)), Some(cached)) => {
+                    cache.insert(cached.clone(), namespace, key);
+                    generated.push(cached);
+                }
+                (Ok(_), _) => {},
+                (Err(error), cached) => {
+                    if let Some(cached) = cached {
+                        cache.remove(&cached);
+                    }
+                    let path = reference.source_path().unwrap_or(src_dir_path);
+                    diagnostics.push(Diagnostic::from_error(path, Phase::Ref, &error));
+                }
+            }
+        }
+        if all {
+            for cached in cache.stale(namespace, &generated) {
+                let partial = dest_dir.join(cached.strip_prefix(ref_partial_dir(namespace))?);
+                if partial.is_file() {
+                    info!("removing {}, source deleted", partial.display());
+                    std::fs::remove_file(&partial)?;
+                }
+                cache.remove(&cached);
+            }
+        }
+        cache.save(config)?;
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(BuildFailure { diagnostics }.into())
+        }
     }
 }
 
@@ -177,9 +340,22 @@ mod tests {
         assert_eq!(output, "<audio id=\"audio\" controls><source src=\"/audio/hello.mp3\" type=\"audio/mpeg\">Your browser does not support the audio element. <a href=\"/audio/hello.mp3\" title=\"hello.mp3\" class=\"audio\"><span class=\"fa-solid fa-play\">hello.mp3</span></a></audio>")
     }
 
+    #[test]
+    fn test_ref_audio() {
+        let config = Config::builder().refdir("src/test/data").build().unwrap();
+        let found = ref_audio(&config, "ref/short-sentence.html").unwrap();
+        assert_eq!(found.audio.path, PathBuf::from("src/test/data/short-sentence.mp3"));
+        assert_eq!(found.audio.mime.essence_str(), MP3_MIME_STR);
+        assert_eq!(found.transcript, Some(PathBuf::from("src/test/data/short-sentence.transcript.json")));
+        assert_eq!(found.media_dir, "media");
+        assert!(ref_audio(&config, "ref/short-sentence-no-punctuation.html").is_none());
+        assert!(ref_audio(&config, "archive/ref/short-sentence.html").is_none());
+    }
+
     #[test]
     fn test_write_md() {
         let reference: Ref<'_> = Ref {config: &Config::default(),
+            namespace: None,
             md: Some("src/test/data/short-sentence.md".into()),
             audio: None,
             transcript: None
@@ -201,6 +377,7 @@ mod tests {
         let audio = Some(AudioFile { path, mime });
 
         let reference: Ref<'_> = Ref {config: &Config::default(),
+            namespace: None,
             md: Some("src/test/data/short-sentence.md".into()),
             audio,
             transcript: None
@@ -222,6 +399,7 @@ mod tests {
         let audio = Some(AudioFile { path, mime });
 
         Ref {config,
+            namespace: None,
             md: Some("src/test/data/short-sentence-no-punctuation.md".into()),
             audio,
             transcript: Some("src/test/data/short-sentence-no-punctuation.transcript.json".into()),
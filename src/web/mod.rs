@@ -1,10 +1,31 @@
-use std::path::Path;
+use rayon::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 use tracing::{info, trace};
 use walkdir::WalkDir;
 
 mod audio;
+pub mod cache;
+use cache::BuildCache;
+pub mod deps;
+use deps::{template_name, DepGraph, SITE_PAGES};
+pub mod diagnostic;
+use diagnostic::{Diagnostic, Diagnostics, Phase};
+pub mod feed;
+pub mod manifest;
+use manifest::{Manifest, Skipped};
+pub mod pages;
+pub mod paginate;
+pub mod permalink;
+pub mod publish;
+pub mod sitemap;
+pub mod taxonomy;
 mod document;
-use document::Document;
+use document::{Document, HandlebarsTemplate, HtmlGenerator};
+pub use document::{Outpaths, SiteData};
 mod framework;
 use framework::render_html;
 
@@ -17,22 +38,25 @@ pub mod template;
 pub mod words;
 
 use crate::{
-    config::Context,
+    config::{ref_partial_dir, Context},
     util::*,
 };
 
 
 
-fn build_source_files(context: &Context) -> anyhow::Result<()> {
+fn build_source_files(context: &mut Context) -> anyhow::Result<Manifest> {
     let config: &crate::config::Config = context.config;
     info!("Bulding source files...");
     let walker = WalkDir::new(&config.sourcedir)
         .follow_links(true)
+        .sort_by_file_name()
         .into_iter()
         .filter_entry(|e| {
             !e.is_hidden()
         });
 
+    // directories first, so files can be rendered in any order
+    let mut files = Vec::new();
     for entry_result in walker
     {
         trace!("  entry: {:?}", entry_result);
@@ -41,20 +65,158 @@ fn build_source_files(context: &Context) -> anyhow::Result<()> {
         if path.is_dir() {
             create_destdir(&config.sourcedir, &config.outdir, path)?;
         } else {
-            render_file(context, path)?;
+            files.push(path.to_path_buf());
         }
     }
-    Ok(())
+    // drafts, future and expired pages are left out, along with any earlier output
+    let now = chrono::Local::now();
+    let mut diagnostics = Diagnostics::new(config.fail_fast);
+    let mut published = Vec::new();
+    let mut skipped = Vec::new();
+    for path in files {
+        match publish::unpublished(config, &path, now) {
+            Ok(None) => published.push(path),
+            Ok(Some(reason)) => {
+                info!("skipping {}: {}", path.display(), reason);
+                remove_output(config, &path)?;
+                skipped.push(Skipped { source: path, reason });
+            },
+            Err(e) => diagnostics.record(Diagnostic::from_error(&path, Phase::FrontMatter, &e))?,
+        }
+    }
+    // a bad permalink fails its page, two pages with one outpath fail the second;
+    // each outpath is resolved here once and passed through the rest of the build
+    let mut written: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    let mut outpaths = Outpaths::default();
+    for path in std::mem::take(&mut published) {
+        match Document::from_path(&path).output_path(config) {
+            Ok(outpath) => match written.get(&outpath) {
+                Some(first) => diagnostics.record(Diagnostic {
+                    line: None,
+                    phase: Phase::Render,
+                    message: format!("written to {}, like {}", outpath.display(), first.display()),
+                    path,
+                })?,
+                None => {
+                    written.insert(outpath.clone(), path.clone());
+                    outpaths.insert(path.clone(), outpath);
+                    published.push(path);
+                },
+            },
+            Err(e) => diagnostics.record(Diagnostic::from_error(&path, Phase::FrontMatter, &e))?,
+        }
+    }
+
+    // every page is known before any is rendered, for templates that list them
+    template::set_outpaths(context, outpaths);
+    context.set_pages(pages::collect(config, &context.outpaths, &published)?);
+    let mut cache = BuildCache::load(config)?;
+    let stale = cache.stale(config, &context.outpaths, &published, &context.pages)?;
+    cache.remove_deleted(config)?;
+    let (rendered, result) = render_files(context, &stale);
+    diagnostics.check(result)?;
+    for (path, page) in rendered.iter().filter(|(_, page)| page.time.is_some()) {
+        cache.rendered(config, &context.outpaths, path, &page.links)?;
+    }
+    let render_times = rendered.into_iter()
+        .filter_map(|(path, page)| Some((path, page.time?)))
+        .collect();
+    // listings, feeds and the sitemap are written on every build, the previous
+    // ones are removed first in case their term or feed is gone
+    for output in Manifest::load(config)?.outputs {
+        let outpath = config.outdir.join(&output.path);
+        let generated = [manifest::Generator::Listing, manifest::Generator::Feed, manifest::Generator::Sitemap]
+            .contains(&output.generator);
+        // unless a source file now takes its place, e.g. source/robots.txt
+        let replaced = config.sourcedir.join(&output.path).is_file();
+        if generated && !replaced && outpath.is_file() {
+            std::fs::remove_file(&outpath)?;
+        }
+    }
+    let listings = taxonomy::render(context)?;
+    let feeds = feed::write(context)?;
+    let sitemap = sitemap::write(context)?;
+    diagnostics.finish()?;
+    cache.save(config)?;
+    let mut manifest = Manifest::for_sources(config, &context.outpaths, &published, &render_times)?;
+    manifest.add_listings(config, &listings)?;
+    manifest.add_generated(config, manifest::Generator::Feed, &feeds)?;
+    manifest.add_generated(config, manifest::Generator::Sitemap, &sitemap)?;
+    manifest.skipped = skipped;
+    Ok(manifest)
+}
+
+fn remove_output(config: &crate::config::Config, sourcepath: &Path) -> anyhow::Result<()> {
+    let outpath = Document::from_path(sourcepath).output_path(config)?;
+    if outpath.is_file() {
+        std::fs::remove_file(&outpath)?;
+    }
+    paginate::remove_pages(&outpath, 2)
+}
+
+struct Rendered {
+    // how long it took, None if it failed
+    time: Option<Duration>,
+    // sources the page linked to, see cache::recording_links
+    links: BTreeSet<PathBuf>,
+}
+
+// render on the worker pool, reporting every failure in the order of files,
+// or with --fail-fast only the first one to happen; a page that failed is
+// returned too, with the links it got to, so it renders again once they exist
+fn render_files(context: &Context, files: &[PathBuf]) -> (BTreeMap<PathBuf, Rendered>, anyhow::Result<()>) {
+    let config = context.config;
+    let render = |path: &PathBuf| {
+        let start = Instant::now();
+        let (result, links) = cache::recording_links(|| render_file(context, path));
+        let rendered = Rendered { time: result.is_ok().then(|| start.elapsed()), links };
+        let diagnostic = result.err().map(|e| {
+            let phase = if Document::from_path(path).is_html_source() { Phase::Render } else { Phase::Copy };
+            Diagnostic::from_error(path, phase, &e)
+        });
+        (path.clone(), rendered, diagnostic)
+    };
+    let results: Vec<(PathBuf, Rendered, Option<Diagnostic>)> = if config.fail_fast {
+        let results = files.par_iter()
+            .map(render)
+            .map(|(path, page, diagnostic)| match diagnostic {
+                None => Ok((path, page, None)),
+                failed => Err(Box::new((path, page, failed))),
+            })
+            .collect::<Result<Vec<_>, _>>();
+        results.unwrap_or_else(|failed| vec![*failed])
+    } else {
+        files.par_iter().map(render).collect()
+    };
+    let mut diagnostics = Diagnostics::new(config.fail_fast);
+    let mut rendered = BTreeMap::new();
+    for (path, page, diagnostic) in results {
+        rendered.insert(path, page);
+        if let Some(diagnostic) = diagnostic {
+            if let Err(e) = diagnostics.record(diagnostic) {
+                return (rendered, Err(e));
+            }
+        }
+    }
+    (rendered, diagnostics.finish())
 }
 
+// copies non-template files from the build template directory,
+// which has the built-in theme merged with templatedir
 fn copy_template_assets(context: &Context) -> anyhow::Result<()> {
     let config: &crate::config::Config = context.config;
     info!("Copying template assets...");
-    let walker = WalkDir::new(&config.templatedir)
+    let buildtemplatedir = config.buildtemplatedir();
+    // generated ref partials: ref and each <namespace>/ref directory,
+    // the rest of <namespace> may be theme or template assets
+    let buildrefdirs: Vec<_> = config.ref_roots().iter()
+        .map(|(namespace, _)| buildtemplatedir.join(crate::config::ref_partial_dir(*namespace)))
+        .collect();
+    let walker = WalkDir::new(&buildtemplatedir)
         .follow_links(true)
         .into_iter()
         .filter_entry(|e| {
-            !e.is_hidden()
+            !e.is_hidden() && !buildrefdirs.iter().any(|dir| e.path() == dir)
         });
 
     for entry_result in walker
@@ -65,10 +227,12 @@ fn copy_template_assets(context: &Context) -> anyhow::Result<()> {
         trace!("  entry: {:?}", entry_result);
         let entry = entry_result?;
         let path = entry.path();
-        if path.is_dir() {
-            create_destdir(&config.templatedir, &config.outdir, path)?;
-        } else if path.extension() != Some(template_extension) {
-            std::fs::copy(&path, config.templatedir_outpath(&path)?)?;
+        // directories come with the files in them, so a namespace
+        // directory that only holds ref partials isn't created
+        if path.is_file() && path.extension() != Some(template_extension) {
+            let outpath = config.templatedir_outpath(path)?;
+            outpath.prepare_output()?;
+            std::fs::copy(path, outpath)?;
         }
     }
 
@@ -77,14 +241,154 @@ fn copy_template_assets(context: &Context) -> anyhow::Result<()> {
 
 
 
-pub fn process_files(context: &Context) -> anyhow::Result<()> {
-   build_source_files(&context)?;
-   copy_template_assets(&context)?;
-   Ok(())
+// template assets are copied even if some pages failed,
+// the manifest is only written when everything succeeded
+pub fn process_files(context: &mut Context) -> anyhow::Result<()> {
+   let result = build_source_files(context);
+   copy_template_assets(context)?;
+   let mut manifest = result?;
+   manifest.add_assets(context.config)?;
+   manifest.save(context.config)
 }
 
 
 
+// incremental build for changed paths, as reported by the watcher:
+// re-renders only pages affected by the change and removes
+// outputs of deleted sources
+pub fn process_changes(context: &mut Context, deps: &mut DepGraph, changed: &[PathBuf]) -> anyhow::Result<()> {
+    let config = context.config;
+    let mut rerender: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut changed_templates: BTreeSet<String> = BTreeSet::new();
+    let mut changed_assets: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut layers_changed = false;
+    let mut refs_changed = false;
+    let mut diagnostics = Diagnostics::new(config.fail_fast);
+    let mut deleted: Vec<PathBuf> = Vec::new();
+    // sources added, deleted or written somewhere else: pages that link to them render again
+    let mut moved: BTreeSet<PathBuf> = BTreeSet::new();
+
+    for path in changed {
+        if let Ok(rel_path) = path.strip_prefix(&config.sourcedir) {
+            if rel_path.components().any(|c| c.as_os_str().to_string_lossy().starts_with('.')) {
+                continue;
+            }
+            if path.is_dir() {
+                let new_files = WalkDir::new(path).into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file());
+                rerender.extend(new_files.map(|e| e.into_path()));
+            } else if path.exists() {
+                rerender.insert(path.clone());
+            } else {
+                deleted.push(path.clone());
+                for source in deps.sources_in(path) {
+                    moved.insert(source.clone());
+                    if let Some(outpath) = deps.remove(&source) {
+                        info!("removing {}, source deleted", outpath.display());
+                        let _ = std::fs::remove_file(&outpath);
+                        paginate::remove_pages(&outpath, 2)?;
+                    }
+                }
+                let outdir = config.outpath(path)?;
+                if outdir.is_dir() {
+                    std::fs::remove_dir_all(&outdir)?;
+                }
+            }
+        } else if let Some(layer) = config.templatedirs.iter().find(|dir| path.starts_with(dir)) {
+            let rel_path = path.strip_prefix(layer)?;
+            layers_changed = true;
+            if rel_path.extension() == Some(std::ffi::OsStr::new("hbs")) {
+                changed_templates.insert(template_name(rel_path));
+            } else {
+                changed_assets.insert(rel_path.to_path_buf());
+            }
+        } else if let Some((namespace, refdir)) = config.ref_roots().into_iter()
+                .find(|(_, dir)| path.starts_with(dir)) {
+            diagnostics.check(template::refresh_ref(config, namespace, refdir, path))?;
+            let stem = path.strip_prefix(refdir)?.with_extension("").with_extension("");
+            let partial = ref_partial_dir(namespace).join(stem).with_extension("html.hbs");
+            changed_templates.insert(template_name(&partial));
+            refs_changed = true;
+        }
+    }
+
+    if layers_changed {
+        template::refresh_layers(config)?;
+        for rel_path in &changed_assets {
+            let buildpath = config.buildtemplatedir().join(rel_path);
+            let outpath = config.outdir.join(rel_path);
+            if buildpath.is_file() {
+                outpath.prepare_output()?;
+                std::fs::copy(&buildpath, &outpath)?;
+            } else if outpath.is_file() {
+                std::fs::remove_file(&outpath)?;
+            }
+        }
+    }
+    if layers_changed || refs_changed {
+        context.hbs = template::register(config)?;
+        deps.scan_templates(config)?;
+    }
+    // a page was added, removed or changed its front matter
+    let sources: Vec<PathBuf> = rerender.iter().cloned().collect();
+    let mut pages = context.pages.clone();
+    let mut outpaths = context.outpaths.clone();
+    pages::update(config, &mut pages, &mut outpaths, &sources, &deleted)?;
+    template::set_outpaths(context, outpaths);
+    if pages != context.pages {
+        taxonomy::remove_stale(config, &context.pages, &pages)?;
+        context.set_pages(pages);
+        changed_templates.insert(SITE_PAGES.to_string());
+    }
+    rerender.extend(deps.pages_using(&changed_templates));
+
+    let now = chrono::Local::now();
+    let mut published = Vec::new();
+    for path in rerender {
+        match publish::unpublished(config, &path, now) {
+            Ok(None) => published.push(path),
+            Ok(Some(reason)) => {
+                info!("skipping {}: {}", path.display(), reason);
+                remove_output(config, &path)?;
+            },
+            Err(e) => diagnostics.record(Diagnostic::from_error(&path, Phase::FrontMatter, &e))?,
+        }
+    }
+    let mut rerender = published;
+    for path in &rerender {
+        // a bad permalink is reported by deps.record
+        if let Ok(outpath) = context.outpaths.get(config, path) {
+            outpath.create_all_parent_dir()?;
+        }
+        match deps.record(config, path) {
+            Ok(true) => { moved.insert(path.clone()); },
+            Ok(false) => {},
+            Err(e) => diagnostics.record(Diagnostic::from_error(path, Phase::Render, &e))?,
+        }
+    }
+    for path in deps.pages_linking(&moved) {
+        if !rerender.contains(&path) {
+            rerender.push(path);
+        }
+    }
+    info!("incremental build: {} of {} changed paths to render", rerender.len(), changed.len());
+    let (rendered, result) = render_files(context, &rerender);
+    for (path, page) in rendered {
+        deps.linked(&path, page.links);
+    }
+    diagnostics.check(result)?;
+    // listings depend on every page and on the templates, and are quick to write
+    if !config.taxonomies.is_empty() {
+        taxonomy::render(context)?;
+    }
+    if !config.feeds.is_empty() {
+        feed::write(context)?;
+    }
+    sitemap::write(context)?;
+    diagnostics.finish()
+}
+
 fn render_file<P: AsRef<Path>>(
     context: &Context,
     path: P,
@@ -94,16 +398,666 @@ fn render_file<P: AsRef<Path>>(
     trace!("render_file: {}", sourcepath.display());
     let document = Document::from_path(&path);
     match document.html_generator(&context)? {
-        None => { std::fs::copy(&path, config.outpath(&path)?)?;},
+        None => {
+            let outpath = config.outpath(&path)?;
+            outpath.prepare_output()?;
+            std::fs::copy(&path, outpath)?;
+        },
         Some(html_source) => {
-            let writepath = document.outpath(config)?;
-            let mut writer = std::fs::File::options()
-                .create(true)
-                .write(true)
-                .open(writepath)?;
-            render_html(&context, html_source, &mut writer)?;
+            let writepath = context.outpaths.get(config, sourcepath)?;
+            let Some(pages) = paginate::pages(context, sourcepath, &writepath)? else {
+                return write_html(context, html_source, &writepath);
+            };
+            for page in &pages {
+                let html_source = HtmlGenerator::Template(HandlebarsTemplate::from_page(context, sourcepath, Some(page))?);
+                write_html(context, html_source, &page.outpath)?;
+            }
+            // the collection got shorter
+            paginate::remove_pages(&writepath, pages.len() + 1)?;
         }
 
     }
     Ok(())
 }
+
+fn write_html(context: &Context, html_source: HtmlGenerator, writepath: &Path) -> anyhow::Result<()> {
+    writepath.prepare_output()?;
+    let mut writer = std::fs::File::options()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(writepath)?;
+    render_html(context, html_source, &mut writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::*, setup};
+    use self::diagnostic::BuildFailure;
+
+    #[test]
+    // only pages that use a changed template are rendered again,
+    // outputs of deleted sources are removed
+    fn test_process_changes() {
+        let dir = std::env::temp_dir().join("altwebgen-incremental");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/a.md", "# A");
+        write("source/b.md", "---\nlayout: plain\n---\n# B");
+        write("source/c.md.hbs", "{{> ref/quote.html }}");
+        write("template/default.hbs", "{{> _nav }}{{{ body }}}");
+        write("template/plain.hbs", "{{{ body }}}");
+        write("template/_nav.hbs", "nav");
+        write("ref/quote.md", "a quote");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .refdir(dir.join("ref"))
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        let mut context = setup::init_and_build(&config).unwrap();
+        let mut deps = DepGraph::scan(&config, &mut Diagnostics::default()).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        let mark_stale = |rel_path: &str| std::fs::write(dir.join(".dist").join(rel_path), "stale").unwrap();
+
+        // partial used by the default layout
+        ["a.html", "b.html", "c.html"].iter().for_each(|page| mark_stale(page));
+        write("template/_nav.hbs", "new nav");
+        process_changes(&mut context, &mut deps, &[dir.join("template/_nav.hbs")]).unwrap();
+        assert_eq!(read("a.html"), "new nav<h1>A</h1>\n");
+        assert_eq!(read("b.html"), "stale");
+        assert!(read("c.html").starts_with("new nav"));
+
+        // ref included by one page
+        write("ref/quote.md", "another quote");
+        process_changes(&mut context, &mut deps, &[dir.join("ref/quote.md")]).unwrap();
+        assert!(read("c.html").contains("another quote"));
+
+        // edited and deleted sources
+        mark_stale("a.html");
+        write("source/b.md", "---\nlayout: plain\n---\n# B2");
+        std::fs::remove_file(dir.join("source/a.md")).unwrap();
+        process_changes(&mut context, &mut deps,
+            &[dir.join("source/a.md"), dir.join("source/b.md")]).unwrap();
+        assert_eq!(read("b.html"), "<h1>B2</h1>\n");
+        assert!(!dir.join(".dist/a.html").exists());
+    }
+
+    #[test]
+    // a second build only renders what changed since the first,
+    // unless the cache is turned off
+    fn test_build_cache() {
+        let dir = std::env::temp_dir().join("altwebgen-build-cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/a.md", "# A");
+        write("source/b.md", "# B");
+        write("source/c.md", "# C");
+        write("template/default.hbs", "{{{ body }}}");
+        let builder = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .transcript(Transcript::Off);
+        let config = builder.clone().build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        let mark_stale = |rel_path: &str| std::fs::write(dir.join(".dist").join(rel_path), "stale").unwrap();
+
+        ["a.html", "b.html"].iter().for_each(|page| mark_stale(page));
+        // staged outputs are hard links, the published b.html isn't written through
+        std::fs::hard_link(dir.join(".dist/b.html"), dir.join("published-b.html")).unwrap();
+        write("source/b.md", "# B2");
+        std::fs::remove_file(dir.join("source/c.md")).unwrap();
+        // the context is the build's, with outpaths in outdir rather than staging
+        let context = setup::init_and_build(&config).unwrap();
+        assert_eq!(context.outpaths.get(&config, &dir.join("source/b.md")).unwrap(), dir.join(".dist/b.html"));
+        assert_eq!(context.pages.len(), 2);
+        assert_eq!(read("a.html"), "stale");
+        assert_eq!(read("b.html"), "<h1>B2</h1>\n");
+        assert_eq!(std::fs::read_to_string(dir.join("published-b.html")).unwrap(), "stale");
+        assert!(!dir.join(".dist/c.html").exists());
+        assert!(!dir.join(".dist.staging").exists());
+
+        // a page that links to another renders again when the other one moves
+        write("source/a.md", "[B](b.md)");
+        setup::init_and_build(&config).unwrap();
+        mark_stale("a.html");
+        write("source/b.md", "---\npermalink: /b/\n---\n# B2");
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("a.html"), "<p><a href=\"/b/\">B</a></p>\n");
+        write("source/a.md", "# A");
+
+        // a template change renders every page again
+        write("template/default.hbs", "<main>{{{ body }}}</main>");
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("a.html"), "<main><h1>A</h1>\n</main>");
+
+        mark_stale("a.html");
+        let config = builder.cache(false).build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("a.html"), "<main><h1>A</h1>\n</main>");
+    }
+
+    #[test]
+    // what a page looked up while rendering decides when it renders again:
+    // a link from its layout, or site.pages listed inside {{#with site}}
+    fn test_build_cache_lookups() {
+        let dir = std::env::temp_dir().join("altwebgen-build-cache-lookups");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/about.md", "# About");
+        write("source/a.md", "# A");
+        write("source/list.md", "---\nlayout: list\n---\n");
+        write("template/default.hbs", "<a href=\"{{ url \"about.md\" }}\">about</a>{{{ body }}}");
+        write("template/list.hbs", "{{#with site}}{{#each pages}}[{{ source }}]{{/each}}{{/with}}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        let mark_stale = |rel_path: &str| std::fs::write(dir.join(".dist").join(rel_path), "stale").unwrap();
+
+        ["a.html", "list.html"].iter().for_each(|page| mark_stale(page));
+        write("source/b.md", "# B");
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("a.html"), "stale");
+        assert_eq!(read("list.html"), "[a.md][about.md][b.md][list.md]");
+
+        write("source/about.md", "---\npermalink: /about/\n---\n# About");
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("a.html"), "<a href=\"/about/\">about</a><h1>A</h1>\n");
+    }
+
+    #[test]
+    // every output is listed once the build has succeeded,
+    // pages skipped by the cache keep their render time
+    fn test_manifest() {
+        let dir = std::env::temp_dir().join("altwebgen-manifest");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/a.md", "---\nlayout: plain\n---\n# A");
+        write("source/b.html.hbs", "{{> ref/quote.html }}");
+        write("source/notes.txt", "notes");
+        write("template/plain.hbs", "{{{ body }}}");
+        write("template/site.css", "body {}");
+        write("ref/quote.md", "a quote");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .refdir(dir.join("ref"))
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let manifest = Manifest::load(&config).unwrap();
+        let output = |path: &str| manifest.outputs.iter()
+            .find(|output| output.path == Path::new(path))
+            .unwrap_or_else(|| panic!("{} not in manifest", path))
+            .clone();
+
+        let a = output("a.html");
+        assert_eq!(a.source, Some(dir.join("source/a.md")));
+        assert_eq!(a.generator, manifest::Generator::Markdown);
+        assert_eq!(a.layout.as_deref(), Some("plain"));
+        assert_eq!(a.size, "<h1>A</h1>\n".len() as u64);
+        assert!(a.render_ms.is_some());
+        assert_eq!(output("b.html").refs, vec!["ref/quote.html"]);
+        assert_eq!(output("notes.txt").generator, manifest::Generator::Asset);
+        let css = output("site.css");
+        assert_eq!(css.source, Some(dir.join("template/site.css")));
+        assert_eq!(css.sha256.len(), 64);
+
+        setup::init_and_build(&config).unwrap();
+        let again = Manifest::load(&config).unwrap();
+        assert_eq!(again.outputs.len(), manifest.outputs.len());
+        assert_eq!(again.outputs.iter().find(|o| o.path == Path::new("a.html")).unwrap().render_ms, a.render_ms);
+    }
+
+    #[test]
+    // every page sees the others as site.pages, in a full build, a cached one
+    // and an incremental one: only pages that list them render again
+    fn test_site_pages() {
+        let dir = std::env::temp_dir().join("altwebgen-site-pages");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/index.html.hbs", "---\ntitle: Home\n---\n{{#each site.pages}}[{{ title }} {{ url }}]{{/each}}");
+        write("source/a.md", "---\ntitle: A\n---\n# A");
+        write("source/b.md", "---\ntitle: B\ndraft: true\n---\n# B");
+        write("template/default.hbs", "{{{ body }}}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        let mark_stale = |rel_path: &str| std::fs::write(dir.join(".dist").join(rel_path), "stale").unwrap();
+        assert_eq!(read("index.html"), "[A /a.html][Home /]");
+
+        mark_stale("a.html");
+        write("source/b.md", "---\ntitle: B\n---\n# B");
+        let mut context = setup::init_and_build(&config).unwrap();
+        assert_eq!(read("index.html"), "[A /a.html][B /b.html][Home /]");
+        assert_eq!(read("a.html"), "stale");
+
+        let mut deps = DepGraph::scan(&config, &mut Diagnostics::default()).unwrap();
+        write("source/a.md", "---\ntitle: A2\n---\n# A");
+        process_changes(&mut context, &mut deps, &[dir.join("source/a.md")]).unwrap();
+        assert_eq!(read("index.html"), "[A2 /a.html][B /b.html][Home /]");
+        assert_eq!(read("b.html"), "<h1>B</h1>\n");
+    }
+
+    #[test]
+    // a page for every tag and person, and an index of each; a term
+    // that goes away takes its page with it, in either kind of build
+    fn test_taxonomies() {
+        let dir = std::env::temp_dir().join("altwebgen-taxonomies");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/a.md", "---\ntitle: A\ntags: [Audio, hypertext]\n---\n# A");
+        write("source/b.md.hbs", "---\ntitle: B\ntags: audio\n---\n{{ person \"Ada Lovelace\" }}");
+        write("source/people/index.md", "# Everyone");
+        write("template/default.hbs", "{{{ body }}}");
+        write("template/person.hbs", "{{ title }} {{ path_to_root }}: {{#each term.pages}}{{ title }}{{/each}}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .taxonomy("tags", Taxonomy::default())
+            .taxonomy("people", Taxonomy { layout: Some(String::from("person")), index_layout: None })
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        let mut context = setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        let audio = read("tags/audio.html");
+        assert!(audio.contains("<h1>Audio</h1>"));
+        assert!(audio.contains("<a href=\"/a.html\">A</a>") && audio.contains("<a href=\"/b.html\">B</a>"));
+        assert!(read("tags/index.html").contains("<a href=\"/tags/hypertext.html\">hypertext</a> (1)"));
+        assert_eq!(read("people/ada-lovelace.html"), "Ada Lovelace ../: B");
+        assert_eq!(read("people/index.html"), "<h1>Everyone</h1>\n");
+        let manifest = Manifest::load(&config).unwrap();
+        let listing = manifest.outputs.iter().find(|o| o.path == Path::new("tags/audio.html")).unwrap();
+        assert_eq!(listing.generator, manifest::Generator::Listing);
+
+        write("source/a.md", "---\ntitle: A\ntags: [audio]\n---\n# A");
+        setup::init_and_build(&config).unwrap();
+        assert!(!dir.join(".dist/tags/hypertext.html").exists());
+
+        let mut deps = DepGraph::scan(&config, &mut Diagnostics::default()).unwrap();
+        write("source/a.md", "---\ntitle: A\ntags: [memex]\n---\n# A");
+        process_changes(&mut context, &mut deps, &[dir.join("source/a.md")]).unwrap();
+        assert!(read("tags/memex.html").contains("<a href=\"/a.html\">A</a>"));
+        assert!(!read("tags/audio.html").contains("/a.html"));
+    }
+
+    #[test]
+    // the person helper links to the person's page, also under a prefix
+    fn test_person_links() {
+        let dir = std::env::temp_dir().join("altwebgen-person-links");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/b.md.hbs", "---\ntitle: B\n---\n{{ person \"Ada Lovelace\" }}");
+        write("template/default.hbs", "{{{ body }}}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .prefix("/blog/")
+            .taxonomy("people", Taxonomy::default())
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let page = std::fs::read_to_string(dir.join(".dist/b.html")).unwrap();
+        assert!(page.contains("href='/blog/people/ada-lovelace.html'"));
+        let report = crate::check::check(&config).unwrap();
+        assert!(report.links > 0);
+        assert_eq!(report.dangling, Vec::new());
+    }
+
+    #[test]
+    // Atom and JSON feeds of a section, newest first, and a podcast feed of
+    // the pages with ref audio, linking the audio and its transcript
+    fn test_feeds() {
+        let dir = std::env::temp_dir().join("altwebgen-feeds");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/index.md", "# Home");
+        write("source/blog/index.md", "# Blog");
+        write("source/blog/old.md", "---\ntitle: Old\ndate: 2024-01-01\n---\nFirst <b>words</b>.\n\nMore.");
+        write("source/blog/new.md.hbs", "---\ntitle: New & Improved\ndate: 2024-06-01\nsummary: Listen\n---\n{{> ref/short-sentence.html }}");
+        write("source/blog/hidden.md", "---\ntitle: Hidden\nfeed: false\n---\nx");
+        write("source/other.md", "---\ntitle: Other\n---\nx");
+        write("template/default.hbs", "{{{ body }}}");
+        for file in ["short-sentence.md", "short-sentence.mp3", "short-sentence.transcript.json"] {
+            let dest = dir.join("ref").join(file);
+            dest.create_all_parent_dir().unwrap();
+            std::fs::copy(Path::new("src/test/data").join(file), dest).unwrap();
+        }
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .refdir(dir.join("ref"))
+            .site("baseurl", serde_json::Value::from("https://example.com/"))
+            .feed("blog", Feed { section: Some(String::from("blog")), title: Some(String::from("Blog")), podcast: true, ..Feed::default() })
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+
+        let atom = read("blog.xml");
+        assert!(atom.contains("<link rel=\"self\" href=\"https://example.com/blog.xml\"/>"));
+        let new = atom.find("<title>New &amp; Improved</title>").unwrap();
+        let old = atom.find("<title>Old</title>").unwrap();
+        assert!(new < old, "newest first");
+        assert!(atom.contains("<summary>First words.</summary>"));
+        assert!(atom.contains("<link rel=\"enclosure\" href=\"https://example.com/media/short-sentence.mp3\" type=\"audio/mpeg\""));
+        assert!(!atom.contains("Hidden") && !atom.contains("Other") && !atom.contains("blog/index.html"));
+
+        let json: serde_json::Value = serde_json::from_str(&read("blog.json")).unwrap();
+        assert_eq!(json["items"].as_array().unwrap().len(), 2);
+        assert_eq!(json["items"][0]["url"], "https://example.com/blog/new.html");
+        assert_eq!(json["items"][0]["attachments"][0]["mime_type"], "audio/mpeg");
+
+        let rss = read("blog.rss");
+        assert!(rss.contains("<podcast:transcript url=\"https://example.com/media/short-sentence.transcript.json\" type=\"application/json\"/>"));
+        assert!(!rss.contains("<title>Old</title>"));
+        assert!(dir.join(".dist/media/short-sentence.transcript.json").is_file());
+        let manifest = Manifest::load(&config).unwrap();
+        let feed = manifest.outputs.iter().find(|o| o.path == Path::new("blog.rss")).unwrap();
+        assert_eq!(feed.generator, manifest::Generator::Feed);
+    }
+
+    #[test]
+    // every page in the sitemap with the prefix, except those with sitemap: false;
+    // robots.txt points to it at the root, unless the site has its own
+    fn test_sitemap() {
+        let dir = std::env::temp_dir().join("altwebgen-sitemap");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/index.md", "# Home");
+        write("source/blog/post.md", "---\ntitle: Post\ndate: 2024-03-01\ntags: [audio]\n---\n# Post");
+        write("source/secret.md", "---\nsitemap: false\n---\n# Secret");
+        write("template/default.hbs", "{{{ body }}}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .prefix("/site/")
+            .site("baseurl", serde_json::Value::from("https://example.com"))
+            .taxonomy("tags", Taxonomy::default())
+            .robots(Robots { disallow: vec![String::from("drafts/")], extra: None })
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+
+        let sitemap = read("sitemap.xml");
+        assert!(sitemap.contains("<loc>https://example.com/site/</loc>"));
+        assert!(sitemap.contains("<loc>https://example.com/site/blog/post.html</loc>\n    <lastmod>2024-03-01T"));
+        assert!(sitemap.contains("<loc>https://example.com/site/tags/audio.html</loc>"));
+        assert!(!sitemap.contains("secret"));
+        assert!(!dir.join(".dist/robots.txt").exists());
+        let manifest = Manifest::load(&config).unwrap();
+        let output = manifest.outputs.iter().find(|o| o.path == Path::new("sitemap.xml")).unwrap();
+        assert_eq!(output.generator, manifest::Generator::Sitemap);
+
+        let config = Config { prefix: String::from("/"), ..config };
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("robots.txt"),
+            "User-agent: *\nDisallow: /drafts/\n\nSitemap: https://example.com/sitemap.xml\n");
+
+        write("source/robots.txt", "User-agent: *\nDisallow: /\n");
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("robots.txt"), "User-agent: *\nDisallow: /\n");
+    }
+
+    #[test]
+    // a listing split over pages, each with its own url and paginator;
+    // pages past the end go away when the collection gets shorter
+    fn test_pagination() {
+        let dir = std::env::temp_dir().join("altwebgen-pagination");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/blog/index.html.hbs", concat!(
+            "---\npaginate: { collection: site.pages, size: 2 }\n---\n",
+            "{{ paginator.current }}/{{ paginator.total }} {{#each paginator.items}}[{{ title }}]{{/each}} ",
+            "{{ paginator.prev }} {{ paginator.next }} {{ url }} {{ path_to_root }}",
+        ));
+        for name in ["a", "b", "c", "d", "e"] {
+            write(&format!("source/blog/{}.md", name), &format!("---\ntitle: {}\n---\n", name.to_uppercase()));
+        }
+        write("template/default.hbs", "{{{ body }}}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .prefix("/site/")
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        assert_eq!(read("blog/index.html"), "1/3 [A][B]  /site/blog/page/2/ /site/blog/ ../");
+        assert_eq!(read("blog/page/2/index.html"),
+            "2/3 [C][D] /site/blog/ /site/blog/page/3/ /site/blog/page/2/ ../../../");
+        assert_eq!(read("blog/page/3/index.html"), "3/3 [E] /site/blog/page/2/  /site/blog/page/3/ ../../../");
+        let manifest = Manifest::load(&config).unwrap();
+        let page = manifest.outputs.iter().find(|o| o.path == Path::new("blog/page/3/index.html")).unwrap();
+        assert_eq!(page.source, Some(dir.join("source/blog/index.html.hbs")));
+
+        std::fs::remove_file(dir.join("source/blog/e.md")).unwrap();
+        setup::init_and_build(&config).unwrap();
+        assert!(read("blog/page/2/index.html").ends_with("/site/blog/  /site/blog/page/2/ ../../../"));
+        assert!(!dir.join(".dist/blog/page/3").exists());
+    }
+
+    #[test]
+    // drafts and future pages are left out of a build unless asked for,
+    // and listed as skipped in the manifest
+    fn test_unpublished_pages() {
+        let dir = std::env::temp_dir().join("altwebgen-unpublished");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source")).unwrap();
+        std::fs::write(dir.join("source/a.md"), "# A").unwrap();
+        std::fs::write(dir.join("source/draft.md"), "---\ndraft: true\n---\n# Draft").unwrap();
+        std::fs::write(dir.join("source/later.md.hbs"), "---\ndate: 2999-01-01\n---\n# Later").unwrap();
+        let builder = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")]);
+        let config = builder.clone().drafts(true).future(true).build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        assert!(dir.join(".dist/draft.html").exists());
+        assert!(dir.join(".dist/later.html").exists());
+
+        let config = builder.build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        assert!(dir.join(".dist/a.html").exists());
+        assert!(!dir.join(".dist/draft.html").exists());
+        assert!(!dir.join(".dist/later.html").exists());
+        let skipped = Manifest::load(&config).unwrap().skipped;
+        assert_eq!(skipped, vec![
+            Skipped { source: dir.join("source/draft.md"), reason: publish::Unpublished::Draft },
+            Skipped { source: dir.join("source/later.md.hbs"),
+                reason: publish::Unpublished::Future(String::from("2999-01-01")) },
+        ]);
+    }
+
+    #[test]
+    // a site-wide permalink pattern gives pretty urls, index pages stay put,
+    // and a page that moves leaves nothing behind at its old outpath
+    fn test_permalinks() {
+        let dir = std::env::temp_dir().join("altwebgen-permalinks");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source/blog")).unwrap();
+        std::fs::create_dir_all(dir.join("template")).unwrap();
+        std::fs::write(dir.join("template/default.hbs"), "{{ url }} {{ path_to_root }}").unwrap();
+        std::fs::write(dir.join("source/index.md"), "# Home").unwrap();
+        std::fs::write(dir.join("source/blog/Hello World.md"), "# Hello").unwrap();
+        std::fs::write(dir.join("source/blog/b.md"), "---\npermalink: /:slug/\n---\n# B").unwrap();
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .prefix("/site/")
+            .permalink("/:section/:slug/")
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        assert_eq!(read("index.html"), "/site/ ");
+        assert_eq!(read("blog/hello-world/index.html"), "/site/blog/hello-world/ ../../");
+        assert_eq!(read("b/index.html"), "/site/b/ ../");
+
+        std::fs::write(dir.join("source/blog/b.md"), "---\npermalink: /moved.html\n---\n# B").unwrap();
+        setup::init_and_build(&config).unwrap();
+        assert_eq!(read("moved.html"), "/site/moved.html ");
+        assert!(!dir.join(".dist/b/index.html").exists());
+
+        std::fs::write(dir.join("source/blog/c.md"), "---\npermalink: /moved.html\n---\n# C").unwrap();
+        let err = setup::init_and_build(&config).unwrap_err();
+        let failure = err.downcast_ref::<BuildFailure>().expect("build failure");
+        assert_eq!(failure.diagnostics.len(), 1);
+        assert_eq!(failure.diagnostics[0].path, dir.join("source/blog/c.md"));
+    }
+
+    #[test]
+    // in the dev server, a page renders again when a page it links to moves,
+    // and a broken link is fixed by creating the missing source
+    fn test_process_changes_links() {
+        let dir = std::env::temp_dir().join("altwebgen-process-links");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("template/default.hbs", "{{{ body }}}");
+        write("source/a.md", "[B](b.md)");
+        write("source/b.md", "# B");
+        write("source/c.html.hbs", "{{ url \"b.md\" }}");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .build().unwrap();
+        let mut context = setup::init_and_build(&config).unwrap();
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        assert_eq!(read("c.html"), "/b.html");
+
+        // links from the last build are known without rendering again
+        let mut deps = DepGraph::scan(&config, &mut Diagnostics::default()).unwrap();
+        write("source/b.md", "---\npermalink: /bee/\n---\n# B");
+        process_changes(&mut context, &mut deps, &[dir.join("source/b.md")]).unwrap();
+        assert!(read("a.html").contains("href=\"/bee/\""));
+        assert_eq!(read("c.html"), "/bee/");
+
+        write("source/d.md", "[E](e.md)");
+        let err = process_changes(&mut context, &mut deps, &[dir.join("source/d.md")]).unwrap_err();
+        assert!(err.downcast_ref::<BuildFailure>().is_some());
+        write("source/e.md", "# E");
+        process_changes(&mut context, &mut deps, &[dir.join("source/e.md")]).unwrap();
+        assert!(read("d.html").contains("href=\"/e.html\""));
+
+        // and broken again once it's gone
+        std::fs::remove_file(dir.join("source/e.md")).unwrap();
+        let err = process_changes(&mut context, &mut deps, &[dir.join("source/e.md")]).unwrap_err();
+        let failure = err.downcast_ref::<BuildFailure>().expect("build failure");
+        assert_eq!(failure.diagnostics[0].path, dir.join("source/d.md"));
+    }
+
+    #[test]
+    // pages render in parallel, every failure is reported in source order
+    // with file, line and phase, and the previous site is left in place
+    fn test_render_files_diagnostics() {
+        let dir = std::env::temp_dir().join("altwebgen-render-errors");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("source")).unwrap();
+        std::fs::write(dir.join("source/a.md"), "# fine").unwrap();
+        let builder = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")]);
+        let config = builder.clone().build().unwrap();
+        setup::init_and_build(&config).unwrap();
+
+        std::fs::write(dir.join("source/a.md"), "# changed").unwrap();
+        std::fs::write(dir.join("source/b.md.hbs"), "{{> missing_b }}").unwrap();
+        std::fs::write(dir.join("source/c.md"), "---\ntitle: c\ntags: [\n---\n# C").unwrap();
+        std::fs::write(dir.join("source/d.html.hbs"), "---\ntitle: d\n---\n<p>\n{{#if}}</p>").unwrap();
+        std::fs::write(dir.join("source/e.md"), "# also fine").unwrap();
+        let err = setup::init_and_build(&config).unwrap_err();
+        let failure = err.downcast_ref::<BuildFailure>().expect("build failure");
+        let failed: Vec<_> = failure.diagnostics.iter()
+            .map(|d| (d.path.strip_prefix(&config.sourcedir).unwrap().to_path_buf(), d.phase))
+            .collect();
+        assert_eq!(failed, vec![
+            (PathBuf::from("b.md.hbs"), Phase::Render),
+            (PathBuf::from("c.md"), Phase::FrontMatter),
+            (PathBuf::from("d.html.hbs"), Phase::Render),
+        ]);
+        assert_eq!(failure.diagnostics[2].line, Some(5));
+        assert!(std::fs::read_to_string(dir.join(".dist/a.html")).unwrap().contains("fine"));
+        assert!(!dir.join(".dist/e.html").exists());
+        assert!(!dir.join(".dist.staging").exists());
+
+        // only the first failure
+        let config = builder.fail_fast(true).build().unwrap();
+        let err = setup::init_and_build(&config).unwrap_err();
+        assert_eq!(err.downcast_ref::<BuildFailure>().unwrap().diagnostics.len(), 1);
+
+        // once fixed, the cache from the failed builds isn't used
+        for name in ["b.md.hbs", "c.md", "d.html.hbs"] {
+            std::fs::remove_file(dir.join("source").join(name)).unwrap();
+        }
+        setup::init_and_build(&config).unwrap();
+        assert!(std::fs::read_to_string(dir.join(".dist/a.html")).unwrap().contains("changed"));
+        assert!(dir.join(".dist/e.html").exists());
+    }
+}
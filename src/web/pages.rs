@@ -0,0 +1,162 @@
+// index of the site's pages, collected before any page is rendered and
+// available to every template as `site.pages`: each published page's
+// front matter with its `url` and `source` path, in source order;
+// with a people taxonomy, `people` also has everyone the page mentions
+use serde_json::Value;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+use crate::{
+    config::Config,
+    util::*,
+    web::{
+        document::{read_source, Document, Outpaths},
+        permalink, publish, taxonomy,
+    },
+};
+
+pub fn collect(config: &Config, outpaths: &Outpaths, files: &[PathBuf]) -> anyhow::Result<Vec<Attributes>> {
+    let mut pages = Vec::new();
+    for path in files {
+        let document = Document::from_path(path);
+        let outpath = outpaths.get(config, path)?;
+        if !document.is_html_source() || outpath.extension() != Some(std::ffi::OsStr::new("html")) {
+            continue;
+        }
+        let mut page = match document.mime.subtype().as_str() {
+            "html" => Attributes::new(),
+            "x-handlebars-template" if config.taxonomies.contains_key(taxonomy::PEOPLE) => {
+                let (mut attr, content) = read_source(path)?;
+                taxonomy::add_people(&mut attr, &content);
+                attr
+            },
+            _ => read_source(path)?.0,
+        };
+        page.entry("url").or_insert(Value::String(permalink::url(config, &outpath)?));
+        let source = path.strip_prefix(&config.sourcedir)?.to_string_lossy().to_string();
+        page.insert("source".into(), Value::String(source));
+        pages.push(page);
+    }
+    Ok(pages)
+}
+
+// every published page in sourcedir, for the dev server to start from, with
+// where each published source is written; pages that can't be read are left out,
+// rendering them reports the error
+pub fn collect_all(config: &Config) -> anyhow::Result<(Vec<Attributes>, Outpaths)> {
+    let now = chrono::Local::now();
+    let walker = WalkDir::new(&config.sourcedir)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !e.is_hidden());
+    let mut files = Vec::new();
+    let mut outpaths = Outpaths::default();
+    for entry in walker {
+        let entry = entry?;
+        let path = entry.into_path();
+        if !path.is_file() || !matches!(publish::unpublished(config, &path, now), Ok(None)) {
+            continue;
+        }
+        if let Ok(outpath) = Document::from_path(&path).output_path(config) {
+            outpaths.insert(path.clone(), outpath);
+            files.push(path);
+        }
+    }
+    Ok((collect(config, &outpaths, &files)?, outpaths))
+}
+
+// an incremental build's site.pages and outpaths: sources that changed are
+// read again, sources under a deleted path are dropped, the rest are kept
+pub fn update(config: &Config, pages: &mut Vec<Attributes>, outpaths: &mut Outpaths, changed: &[PathBuf], deleted: &[PathBuf]) -> anyhow::Result<()> {
+    let source = |page: &Attributes| page.get("source")
+        .and_then(Value::as_str)
+        .map(|source| config.sourcedir.join(source))
+        .unwrap_or_default();
+    pages.retain(|page| {
+        let source = source(page);
+        !changed.contains(&source) && !deleted.iter().any(|path| source.starts_with(path))
+    });
+    for path in deleted {
+        outpaths.remove_under(path);
+    }
+    let now = chrono::Local::now();
+    for path in changed {
+        outpaths.remove(path);
+        if !path.is_file() || !matches!(publish::unpublished(config, path, now), Ok(None)) {
+            continue;
+        }
+        let Ok(outpath) = Document::from_path(path).output_path(config) else { continue };
+        outpaths.insert(path.clone(), outpath);
+        // in source order, as collect_all walks sourcedir
+        if let Some(page) = collect(config, outpaths, std::slice::from_ref(path))?.pop() {
+            let at = pages.partition_point(|other| source(other) < *path);
+            pages.insert(at, page);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let dir = std::env::temp_dir().join("altwebgen-pages");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("source/index.md", "# Home"),
+            ("source/blog/a.md", "---\ntitle: A\ndate: 2024-01-31\n---\n# A"),
+            ("source/blog/b.html.hbs", "---\ntitle: B\npermalink: /b/\n---\n<p>B</p>"),
+            ("source/blog/draft.md", "---\ndraft: true\n---\n# Draft"),
+            ("source/style.css", "body {}"),
+        ] {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .build().unwrap();
+        let (pages, outpaths) = collect_all(&config).unwrap();
+        let urls: Vec<&str> = pages.iter().map(|page| page["url"].as_str().unwrap()).collect();
+        assert_eq!(urls, vec!["/blog/a.html", "/b/", "/"]);
+        assert_eq!(pages[0]["title"], "A");
+        assert_eq!(pages[0]["date"], "2024-01-31");
+        assert_eq!(pages[0]["source"], "blog/a.md");
+        assert_eq!(outpaths.get(&config, &dir.join("source/blog/b.html.hbs")).unwrap(), dir.join(".dist/b/index.html"));
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = std::env::temp_dir().join("altwebgen-pages-update");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write = |rel_path: &str, contents: &str| {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("source/index.md", "# Home");
+        write("source/blog/a.md", "---\ntitle: A\n---\n# A");
+        write("source/blog/b.md", "---\ntitle: B\n---\n# B");
+        write("source/old/c.md", "# C");
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .build().unwrap();
+        let (mut pages, mut outpaths) = collect_all(&config).unwrap();
+
+        write("source/blog/a.md", "---\ntitle: A2\npermalink: /a/\n---\n# A");
+        write("source/blog/aa.md", "---\ntitle: AA\n---\n# AA");
+        write("source/blog/b.md", "---\ndraft: true\n---\n# B");
+        std::fs::remove_dir_all(dir.join("source/old")).unwrap();
+        let changed = [dir.join("source/blog/a.md"), dir.join("source/blog/aa.md"), dir.join("source/blog/b.md")];
+        update(&config, &mut pages, &mut outpaths, &changed, &[dir.join("source/old")]).unwrap();
+
+        let (expected, _) = collect_all(&config).unwrap();
+        assert_eq!(pages, expected);
+        assert_eq!(outpaths.get(&config, &dir.join("source/blog/a.md")).unwrap(), dir.join(".dist/a/index.html"));
+    }
+}
@@ -0,0 +1,169 @@
+// a template source that lists a long collection can split it over pages:
+//   paginate: { collection: site.pages, size: 10 }
+// the first page is written where the source would be, blog/index.html,
+// the rest to blog/page/2/index.html and so on (archive.html → archive/page/2/),
+// each with `paginator`: its items, current, total and prev/next urls
+use anyhow::{bail, Context as _};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::Context,
+    util::Attributes,
+    web::{
+        document::{page_data, read_source, Document},
+        permalink,
+    },
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Paginate {
+    // where the list is in the page's template data, e.g. site.pages
+    pub collection: String,
+    // items on each page
+    pub size: usize,
+    // last item first, e.g. newest posts
+    pub reverse: bool,
+}
+
+impl Paginate {
+    pub fn from_front_matter(attr: &Attributes) -> anyhow::Result<Option<Paginate>> {
+        let Some(value) = attr.get("paginate") else { return Ok(None) };
+        let expected = || format!("paginate should look like {{ collection: site.pages, size: 10 }}, found: {}", value);
+        let Value::Object(settings) = value else { bail!(expected()) };
+        if let Some(key) = settings.keys().find(|key| !["collection", "size", "reverse"].contains(&key.as_str())) {
+            bail!("paginate has unknown key '{}', expected collection, size or reverse", key);
+        }
+        let collection = settings.get("collection").and_then(Value::as_str).with_context(expected)?;
+        let size = settings.get("size").and_then(Value::as_u64).filter(|size| *size > 0).with_context(expected)?;
+        let reverse = settings.get("reverse").map(|reverse| reverse.as_bool().with_context(expected))
+            .transpose()?
+            .unwrap_or(false);
+        Ok(Some(Paginate { collection: collection.to_string(), size: size as usize, reverse }))
+    }
+}
+
+// one output of a paginated source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    pub outpath: PathBuf,
+    pub url: String,
+    pub paginator: Value,
+}
+
+// the pages a source is split into, none if it doesn't paginate;
+// outpath is where the source itself is written
+pub fn pages(context: &Context, sourcepath: &Path, outpath: &Path) -> anyhow::Result<Option<Vec<Page>>> {
+    let config = context.config;
+    if Document::from_path(sourcepath).mime.subtype() != "x-handlebars-template" {
+        return Ok(None);
+    }
+    let (attr, _) = read_source(sourcepath)?;
+    let Some(paginate) = Paginate::from_front_matter(&attr)? else { return Ok(None) };
+    let data = Value::Object(page_data(context, sourcepath, attr)?);
+    let mut keys = paginate.collection.split('.');
+    let found = keys.next()
+        .and_then(|key| match key {
+            "site" => Some(context.site.value()),
+            _ => data.get(key),
+        })
+        .and_then(|value| keys.try_fold(value, |value, key| value.get(key)));
+    let Some(Value::Array(collection)) = found else {
+        bail!("paginate: collection '{}' is not a list", paginate.collection);
+    };
+    // an archive that is in its own collection doesn't list itself
+    let mut items: Vec<&Value> = collection.iter()
+        .filter(|item| data.get("url").is_none() || item.get("url") != data.get("url"))
+        .collect();
+    if paginate.reverse {
+        items.reverse();
+    }
+    let chunks: Vec<&[&Value]> = match items.is_empty() {
+        true => vec![&[]],
+        false => items.chunks(paginate.size).collect(),
+    };
+    let total = chunks.len();
+    let outpaths: Vec<PathBuf> = (1..=total).map(|number| page_outpath(outpath, number)).collect();
+    let urls: Vec<String> = outpaths.iter()
+        .map(|outpath| permalink::url(config, outpath))
+        .collect::<anyhow::Result<_>>()?;
+    let numbered: Vec<Value> = urls.iter().enumerate()
+        .map(|(i, url)| json!({ "number": i + 1, "url": url }))
+        .collect();
+    let pages = chunks.into_iter().enumerate().map(|(i, chunk)| Page {
+        outpath: outpaths[i].clone(),
+        url: urls[i].clone(),
+        paginator: json!({
+            "items": chunk,
+            "current": i + 1,
+            "total": total,
+            "size": paginate.size,
+            "total_items": items.len(),
+            "prev": if i > 0 { Some(&urls[i - 1]) } else { None },
+            "next": urls.get(i + 1),
+            "first": urls[0],
+            "last": urls[total - 1],
+            "pages": numbered,
+        }),
+    }).collect();
+    Ok(Some(pages))
+}
+
+// blog/index.html → blog/page/2/index.html, archive.html → archive/page/2/index.html
+fn page_outpath(outpath: &Path, number: usize) -> PathBuf {
+    if number == 1 {
+        return outpath.to_path_buf();
+    }
+    let base = match outpath.file_name().is_some_and(|name| name == "index.html") {
+        true => outpath.parent().unwrap_or(Path::new("")).to_path_buf(),
+        false => outpath.with_extension(""),
+    };
+    base.join("page").join(number.to_string()).join("index.html")
+}
+
+// pages after the first that an earlier build wrote for outpath
+pub fn extra_pages(outpath: &Path) -> Vec<PathBuf> {
+    (2..).map(|number| page_outpath(outpath, number))
+        .take_while(|path| path.is_file())
+        .collect()
+}
+
+// pages from `from` on, once the collection is shorter or the source is gone
+pub fn remove_pages(outpath: &Path, from: usize) -> anyhow::Result<()> {
+    let from = from.max(2);
+    for path in (from..).map(|number| page_outpath(outpath, number)).take_while(|path| path.is_file()) {
+        std::fs::remove_file(&path)?;
+        // page/<n>, then page once it is empty
+        for dir in path.ancestors().skip(1).take(2) {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, util::parse_yaml_attributes};
+
+    #[test]
+    fn test_paginate_front_matter() {
+        let parse = |yaml: &str| Paginate::from_front_matter(&parse_yaml_attributes(yaml).unwrap());
+        assert_eq!(parse("title: a").unwrap(), None);
+        assert_eq!(parse("paginate: { collection: site.pages, size: 2, reverse: true }").unwrap(),
+            Some(Paginate { collection: String::from("site.pages"), size: 2, reverse: true }));
+        assert!(parse("paginate: site.pages").is_err());
+        assert!(parse("paginate: { collection: site.pages, size: 0 }").is_err());
+        assert!(parse("paginate: { collection: site.pages, size: 2, per_page: 3 }").is_err());
+    }
+
+    #[test]
+    fn test_page_outpath() {
+        let config = Config::default();
+        let index = config.outdir.join("blog/index.html");
+        assert_eq!(page_outpath(&index, 1), index);
+        assert_eq!(page_outpath(&index, 2), config.outdir.join("blog/page/2/index.html"));
+        assert_eq!(page_outpath(&config.outdir.join("archive.html"), 3),
+            config.outdir.join("archive/page/3/index.html"));
+    }
+}
@@ -0,0 +1,195 @@
+// where a page is written and the url it is served at:
+// by default its source path, foo.md → foo.html; a `permalink` in front matter
+// or the site-wide pattern moves it, e.g. "/:section/:slug/", and a permalink
+// ending in / (or without an extension) is written as <dir>/index.html
+use anyhow::{bail, Context as _};
+use ::slug::slugify;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::Config,
+    util::Attributes,
+    web::{cache, document::Outpaths, publish::parse_date},
+};
+
+// :path     source directory, relative to sourcedir
+// :section  its first directory
+// :slug     front matter `slug`, or the source file name, slugified
+// :year, :month, :day  from front matter `date`
+const PLACEHOLDERS: [&str; 6] = ["path", "section", "slug", "year", "month", "day"];
+
+pub fn check_pattern(pattern: &str) -> anyhow::Result<()> {
+    for name in placeholders(pattern) {
+        if !PLACEHOLDERS.contains(&name) {
+            bail!("permalink '{}' has unknown placeholder ':{}', expected one of: :{}",
+                pattern, name, PLACEHOLDERS.join(", :"));
+        }
+    }
+    if pattern.split('/').any(|segment| segment == "..") {
+        bail!("permalink '{}' contains '..'", pattern);
+    }
+    Ok(())
+}
+
+fn placeholders(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern.split(':').skip(1)
+        .map(|rest| rest.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).next().unwrap_or(""))
+}
+
+// outpath of a page with front matter attr, given the one its source path maps to;
+// index pages keep theirs unless they set a permalink of their own
+pub fn apply(config: &Config, sourcepath: &Path, outpath: PathBuf, attr: &Attributes) -> anyhow::Result<PathBuf> {
+    let pattern = match attr.get("permalink") {
+        None | Some(Value::Null) => match &config.permalink {
+            Some(pattern) if file_stem(sourcepath) != "index" => pattern.as_str(),
+            _ => return Ok(outpath),
+        },
+        Some(Value::String(permalink)) => {
+            check_pattern(permalink)?;
+            permalink.as_str()
+        },
+        Some(other) => bail!("permalink should be a path like /about/, found: {}", other),
+    };
+    let expanded = expand(config, sourcepath, pattern, attr)?;
+    let segments: Vec<&str> = expanded.split('/').filter(|segment| !segment.is_empty()).collect();
+    let mut path = config.outdir.clone();
+    path.extend(&segments);
+    let is_file = !expanded.ends_with('/')
+        && segments.last().is_some_and(|last| Path::new(last).extension().is_some());
+    if !is_file {
+        path.push("index.html");
+    }
+    Ok(path)
+}
+
+// outpath of a page the build generates, e.g. a taxonomy term, placed like
+// a source at rel_path in sourcedir would be; a site-wide pattern that needs
+// front matter the page doesn't have, like a date, leaves it at rel_path
+pub fn generated(config: &Config, rel_path: &Path) -> PathBuf {
+    let outpath = config.outdir.join(rel_path);
+    apply(config, &config.sourcedir.join(rel_path), outpath.clone(), &Attributes::new())
+        .unwrap_or(outpath)
+}
+
+fn expand(config: &Config, sourcepath: &Path, pattern: &str, attr: &Attributes) -> anyhow::Result<String> {
+    let rel_dir = sourcepath.strip_prefix(&config.sourcedir)
+        .ok()
+        .and_then(Path::parent)
+        .map(|dir| dir.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut expanded = pattern.to_string();
+    for name in PLACEHOLDERS {
+        let placeholder = format!(":{}", name);
+        if !expanded.contains(&placeholder) {
+            continue;
+        }
+        let value = match name {
+            "path" => rel_dir.join("/"),
+            "section" => rel_dir.first().map(|section| section.to_string()).unwrap_or_default(),
+            "slug" => match attr.get("slug").and_then(Value::as_str) {
+                Some(slug) => slugify(slug),
+                None => slugify(file_stem(sourcepath)),
+            },
+            _ => {
+                let text = attr.get("date").and_then(Value::as_str)
+                    .with_context(|| format!("permalink '{}' uses :{} but the page has no date", pattern, name))?;
+                let date = parse_date("date", text)?;
+                match name {
+                    "year" => date.format("%Y"),
+                    "month" => date.format("%m"),
+                    _ => date.format("%d"),
+                }.to_string()
+            },
+        };
+        expanded = expanded.replace(&placeholder, &value);
+    }
+    Ok(expanded)
+}
+
+// file name up to the first dot: foo.md.hbs → foo
+fn file_stem(sourcepath: &Path) -> &str {
+    sourcepath.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        .unwrap_or("")
+}
+
+// url of an output in outdir, with prefix; <dir>/index.html is served as <dir>/
+pub fn url(config: &Config, outpath: &Path) -> anyhow::Result<String> {
+    let rel_path = outpath.strip_prefix(&config.outdir)?;
+    let mut segments: Vec<String> = rel_path.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    if segments.last().map(String::as_str) == Some("index.html") {
+        segments.pop();
+        segments.push(String::new());
+    }
+    Ok(format!("{}{}", config.prefix, segments.join("/")))
+}
+
+// url of the output a source file is written to,
+// path is relative to sourcedir, e.g. blog/post.md
+pub fn source_url(config: &Config, outpaths: &Outpaths, path: &str) -> anyhow::Result<String> {
+    let sourcepath = config.sourcedir.join(path.trim_start_matches('/'));
+    cache::record_link(&sourcepath);
+    if !sourcepath.is_file() {
+        bail!("no source file {}", sourcepath.display());
+    }
+    let outpath = outpaths.get(config, &sourcepath)?;
+    url(config, &outpath)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::document::Document;
+    use crate::util::parse_yaml_attributes;
+
+    fn outpath(config: &Config, source: &str, yaml: &str) -> String {
+        let sourcepath = config.sourcedir.join(source);
+        let default = Document::from_path(&sourcepath).outpath(config).unwrap();
+        let attr = parse_yaml_attributes(yaml).unwrap();
+        let path = apply(config, &sourcepath, default, &attr).unwrap();
+        path.strip_prefix(&config.outdir).unwrap().display().to_string()
+    }
+
+    #[test]
+    fn test_permalink_front_matter() {
+        let config = Config::default();
+        assert_eq!(outpath(&config, "blog/foo.md", "title: a"), "blog/foo.html");
+        assert_eq!(outpath(&config, "blog/foo.md", "permalink: /about/"), "about/index.html");
+        assert_eq!(outpath(&config, "blog/foo.md", "permalink: /about"), "about/index.html");
+        assert_eq!(outpath(&config, "blog/foo.md", "permalink: /old/about.html"), "old/about.html");
+        assert_eq!(outpath(&config, "blog/foo.md.hbs", "permalink: /:path/:slug/\nslug: Hello World"),
+            "blog/hello-world/index.html");
+    }
+
+    #[test]
+    fn test_permalink_pattern() {
+        let config = Config::builder().permalink("/:section/:year/:slug/").build().unwrap();
+        assert_eq!(outpath(&config, "blog/2024/My Post.md", "date: 2024-03-01"), "blog/2024/my-post/index.html");
+        assert_eq!(outpath(&config, "about.md", "date: 2024-03-01"), "2024/about/index.html");
+        assert_eq!(outpath(&config, "blog/index.md", ""), "blog/index.html");
+        let sourcepath = config.sourcedir.join("blog/undated.md");
+        assert!(apply(&config, &sourcepath, PathBuf::new(), &Attributes::new()).is_err());
+    }
+
+    #[test]
+    fn test_check_pattern() {
+        assert!(check_pattern("/:section/:slug/").is_ok());
+        assert!(check_pattern("/:category/:slug/").is_err());
+        assert!(check_pattern("/../:slug/").is_err());
+        assert!(Config::builder().permalink("/:title/").build().is_err());
+    }
+
+    #[test]
+    fn test_url() {
+        let config = Config::builder().prefix("/blog/").build().unwrap();
+        assert_eq!(url(&config, &config.outdir.join("index.html")).unwrap(), "/blog/");
+        assert_eq!(url(&config, &config.outdir.join("a/index.html")).unwrap(), "/blog/a/");
+        assert_eq!(url(&config, &config.outdir.join("a/b.html")).unwrap(), "/blog/a/b.html");
+    }
+}
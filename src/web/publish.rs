@@ -0,0 +1,142 @@
+// front matter that decides whether a page is published:
+// `draft: true`, a `date` after the build, or an `expires` date before it
+use anyhow::bail;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, path::Path};
+
+use crate::{
+    config::Config,
+    util::Attributes,
+    web::document::{read_source, Document},
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "reason", content = "date")]
+pub enum Unpublished {
+    Draft,
+    // date as written in front matter
+    Future(String),
+    Expired(String),
+}
+
+impl fmt::Display for Unpublished {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unpublished::Draft => write!(f, "draft"),
+            Unpublished::Future(date) => write!(f, "dated {}, in the future", date),
+            Unpublished::Expired(date) => write!(f, "expired {}", date),
+        }
+    }
+}
+
+// why a source isn't rendered by this build, None if it is;
+// drafts, future and expired pages need --drafts, --future and --expired
+// outside dev mode
+pub fn unpublished(config: &Config, sourcepath: &Path, now: DateTime<Local>) -> anyhow::Result<Option<Unpublished>> {
+    // plain html has no front matter
+    let document = Document::from_path(sourcepath);
+    if !matches!(document.mime.subtype().as_str(), "x-handlebars-template" | "markdown") {
+        return Ok(None);
+    }
+    let (attr, _) = read_source(sourcepath)?;
+    check(config, &attr, now)
+}
+
+fn check(config: &Config, attr: &Attributes, now: DateTime<Local>) -> anyhow::Result<Option<Unpublished>> {
+    if !config.drafts && attr.get("draft").and_then(Value::as_bool) == Some(true) {
+        return Ok(Some(Unpublished::Draft));
+    }
+    if !config.future {
+        if let Some((text, date)) = date_attr(attr, "date")? {
+            if date > now {
+                return Ok(Some(Unpublished::Future(text)));
+            }
+        }
+    }
+    if !config.expired {
+        if let Some((text, expires)) = date_attr(attr, "expires")? {
+            if expires <= now {
+                return Ok(Some(Unpublished::Expired(text)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn date_attr(attr: &Attributes, key: &str) -> anyhow::Result<Option<(String, DateTime<Local>)>> {
+    match attr.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(text)) => Ok(Some((text.clone(), parse_date(key, text)?))),
+        Some(other) => bail!("{} should be a date like 2024-01-31, found: {}", key, other),
+    }
+}
+
+// 2024-01-31, 2024-01-31 09:30:00 or with a timezone: 2024-01-31T09:30:00+01:00,
+// dates without a timezone are in local time
+pub fn parse_date(key: &str, text: &str) -> anyhow::Result<DateTime<Local>> {
+    let text = text.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Ok(date.with_timezone(&Local));
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0)));
+    match naive.and_then(|naive| Local.from_local_datetime(&naive).earliest()) {
+        Some(date) => Ok(date),
+        None => bail!("{} '{}' is not a date like 2024-01-31 or 2024-01-31T09:30:00Z", key, text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Mode, util::parse_yaml_attributes};
+
+    fn unpublished_in(mode: Mode, yaml: &str) -> Option<Unpublished> {
+        let config = Config::builder().mode(mode).build().unwrap();
+        let now = parse_date("now", "2024-06-01").unwrap();
+        check(&config, &parse_yaml_attributes(yaml).unwrap(), now).unwrap()
+    }
+
+    #[test]
+    fn test_unpublished_build() {
+        assert_eq!(unpublished_in(Mode::Build, "title: a"), None);
+        assert_eq!(unpublished_in(Mode::Build, "draft: true"), Some(Unpublished::Draft));
+        assert_eq!(unpublished_in(Mode::Build, "draft: false"), None);
+        assert_eq!(unpublished_in(Mode::Build, "date: 2024-01-31"), None);
+        assert_eq!(unpublished_in(Mode::Build, "date: 2024-07-01"),
+            Some(Unpublished::Future(String::from("2024-07-01"))));
+        assert_eq!(unpublished_in(Mode::Build, "expires: 2024-05-30T12:00:00Z"),
+            Some(Unpublished::Expired(String::from("2024-05-30T12:00:00Z"))));
+        assert_eq!(unpublished_in(Mode::Build, "expires: 2025-01-01"), None);
+    }
+
+    #[test]
+    fn test_unpublished_expired_flag() {
+        let config = Config::builder().expired(true).build().unwrap();
+        let now = parse_date("now", "2024-06-01").unwrap();
+        let attr = parse_yaml_attributes("expires: 2024-01-01").unwrap();
+        assert_eq!(check(&config, &attr, now).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unpublished_dev() {
+        for yaml in ["draft: true", "date: 2024-07-01", "expires: 2024-01-01"] {
+            assert_eq!(unpublished_in(Mode::Dev, yaml), None, "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert!(parse_date("date", "2024-01-31").is_ok());
+        assert!(parse_date("date", "2024-01-31 09:30:00").is_ok());
+        assert!(parse_date("date", "2024-01-31T09:30:00+01:00").is_ok());
+        assert!(parse_date("date", "31/01/2024").is_err());
+        let config = Config::default();
+        let attr = parse_yaml_attributes("date: 20240131").unwrap();
+        assert!(check(&config, &attr, Local::now()).is_err());
+    }
+}
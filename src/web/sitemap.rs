@@ -0,0 +1,148 @@
+// sitemap.xml of every html page the build wrote, with lastmod from the
+// front matter updated or date, else when the source last changed, and
+// robots.txt pointing to it; both need the site attribute baseurl, since
+// sitemap urls are absolute; pages with `sitemap: false` are left out;
+// crawlers only read robots.txt at the root, so there's none under a prefix
+use anyhow::Context as _;
+use chrono::{DateTime, Local, SecondsFormat};
+use handlebars::html_escape;
+use serde_json::Value;
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use crate::{
+    config::{Config, Context},
+    util::{Attributes, PathExt},
+    web::{paginate, permalink, publish::parse_date, taxonomy},
+};
+
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+pub const ROBOTS_FILE: &str = "robots.txt";
+
+#[derive(Clone, Debug, PartialEq)]
+struct Url {
+    // absolute
+    loc: String,
+    lastmod: Option<DateTime<Local>>,
+}
+
+// writes sitemap.xml and robots.txt, unless sourcedir has its own robots.txt
+// or the site is under a prefix;
+// returns the files written, none without a baseurl
+pub fn write(context: &Context) -> anyhow::Result<Vec<PathBuf>> {
+    let config = context.config;
+    let Some(baseurl) = config.site_attr.get("baseurl").and_then(Value::as_str) else {
+        warn!("no sitemap.xml: set the site attribute baseurl, e.g. --set baseurl=https://example.com");
+        return Ok(Vec::new());
+    };
+    let baseurl = baseurl.trim_end_matches('/');
+    let mut written = Vec::new();
+    let sitemap_path = config.outdir.join(SITEMAP_FILE);
+    sitemap_path.prepare_output()?;
+    std::fs::write(&sitemap_path, sitemap(&urls(context, baseurl)?))
+        .with_context(|| format!("failed to write {}", sitemap_path.display()))?;
+    written.push(sitemap_path);
+    if config.prefix != "/" {
+        warn!("no robots.txt: crawlers look for it at the root, not under the prefix {}; \
+            add Sitemap: {}{}{} to the root robots.txt", config.prefix, baseurl, config.prefix, SITEMAP_FILE);
+    } else if !config.sourcedir.join(ROBOTS_FILE).is_file() {
+        let robots_path = config.outdir.join(ROBOTS_FILE);
+        robots_path.prepare_output()?;
+        let sitemap_url = format!("{}{}{}", baseurl, config.prefix, SITEMAP_FILE);
+        std::fs::write(&robots_path, robots(config, &sitemap_url))
+            .with_context(|| format!("failed to write {}", robots_path.display()))?;
+        written.push(robots_path);
+    }
+    Ok(written)
+}
+
+// site.pages with the rest of any paginated page, then taxonomy listings
+fn urls(context: &Context, baseurl: &str) -> anyhow::Result<Vec<Url>> {
+    let config = context.config;
+    let mut urls: Vec<Url> = Vec::new();
+    for page in &context.pages {
+        if page.get("sitemap") == Some(&Value::Bool(false)) {
+            continue;
+        }
+        let source = page.get("source").and_then(Value::as_str).unwrap_or_default();
+        let sourcepath = config.sourcedir.join(source);
+        let url = page.get("url").and_then(Value::as_str).unwrap_or_default();
+        let lastmod = lastmod(page, &sourcepath)
+            .with_context(|| format!("{}: front matter date", source))?;
+        urls.push(Url { loc: format!("{}{}", baseurl, url), lastmod });
+        let outpath = context.outpaths.get(config, &sourcepath)?;
+        for extra in paginate::extra_pages(&outpath) {
+            urls.push(Url { loc: format!("{}{}", baseurl, permalink::url(config, &extra)?), lastmod });
+        }
+    }
+    for listing in taxonomy::listings(config, &context.pages)? {
+        let loc = format!("{}{}", baseurl, permalink::url(config, &listing.outpath)?);
+        if listing.outpath.is_file() && !urls.iter().any(|url| url.loc == loc) {
+            urls.push(Url { loc, lastmod: None });
+        }
+    }
+    Ok(urls)
+}
+
+fn lastmod(page: &Attributes, sourcepath: &Path) -> anyhow::Result<Option<DateTime<Local>>> {
+    let date = ["updated", "date"].iter()
+        .find_map(|key| page.get(*key).and_then(Value::as_str).map(|text| (key, text)));
+    match date {
+        Some((key, text)) => Ok(Some(parse_date(key, text)?)),
+        None => Ok(std::fs::metadata(sourcepath).and_then(|meta| meta.modified()).ok().map(DateTime::from)),
+    }
+}
+
+fn sitemap(urls: &[Url]) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    ));
+    for url in urls {
+        xml.push_str("  <url>\n");
+        let _ = writeln!(xml, "    <loc>{}</loc>", html_escape(&url.loc));
+        if let Some(lastmod) = &url.lastmod {
+            let _ = writeln!(xml, "    <lastmod>{}</lastmod>", lastmod.to_rfc3339_opts(SecondsFormat::Secs, true));
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+// disallowed paths are relative to the site, so they get the prefix
+fn robots(config: &Config, sitemap_url: &str) -> String {
+    let mut text = String::from("User-agent: *\n");
+    if config.robots.disallow.is_empty() {
+        text.push_str("Disallow:\n");
+    }
+    for path in &config.robots.disallow {
+        let _ = writeln!(text, "Disallow: {}{}", config.prefix, path.trim_start_matches('/'));
+    }
+    let _ = writeln!(text, "\nSitemap: {}", sitemap_url);
+    if let Some(extra) = &config.robots.extra {
+        let _ = writeln!(text, "\n{}", extra.trim_end());
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Robots;
+
+    #[test]
+    fn test_robots() {
+        let config = Config::builder().prefix("/blog/").build().unwrap();
+        assert_eq!(robots(&config, "https://example.com/blog/sitemap.xml"),
+            "User-agent: *\nDisallow:\n\nSitemap: https://example.com/blog/sitemap.xml\n");
+        let config = Config::builder()
+            .robots(Robots { disallow: vec![String::from("/drafts/")], extra: Some(String::from("User-agent: x\nDisallow: /\n")) })
+            .build().unwrap();
+        assert_eq!(robots(&config, "https://example.com/sitemap.xml"),
+            "User-agent: *\nDisallow: /drafts/\n\nSitemap: https://example.com/sitemap.xml\n\nUser-agent: x\nDisallow: /\n");
+    }
+}
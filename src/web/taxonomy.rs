@@ -0,0 +1,260 @@
+// listing pages for the taxonomies declared in config, e.g. [taxonomies.tags]:
+// each term, a value of the front matter key, gets <name>/<slug>.html with
+// the pages that have it (or wherever the site-wide permalink puts pages),
+// and <name>/index.html lists every term;
+// built from site.pages, so they follow it in full and incremental builds
+use anyhow::{bail, Context as _};
+use ::slug::slugify;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{Config, Context},
+    util::*,
+    web::{document::output_data, permalink},
+};
+
+// pages that use the person helper are indexed under people,
+// the helper links to the person's term page
+pub const PEOPLE: &str = "people";
+
+// partials that render the body of a term's page and of the index,
+// see theme/_taxonomy_term.hbs and theme/_taxonomy_index.hbs
+const TERM_TEMPLATE: &str = "_taxonomy_term";
+const INDEX_TEMPLATE: &str = "_taxonomy_index";
+
+// `{{ person "Ada Lovelace" }}`, with either quote
+fn person_regex() -> &'static Regex {
+    static PERSON: OnceLock<Regex> = OnceLock::new();
+    PERSON.get_or_init(|| {
+        Regex::new(r#"\{\{\{?~?\s*person\s+(?:"([^"]+)"|'([^']+)')"#).expect("person regex")
+    })
+}
+
+// names given to the person helper in some template text
+pub fn person_names(text: &str) -> Vec<String> {
+    person_regex().captures_iter(text)
+        .filter_map(|capture| capture.get(1).or(capture.get(2)))
+        .map(|name| name.as_str().to_string())
+        .collect()
+}
+
+// adds the people a page mentions to the ones in its front matter
+pub fn add_people(page: &mut Attributes, text: &str) {
+    let mentioned = person_names(text);
+    if mentioned.is_empty() {
+        return;
+    }
+    let mut people = match page.remove(PEOPLE) {
+        Some(Value::Array(people)) => people,
+        Some(Value::Null) | None => Vec::new(),
+        Some(other) => vec![other],
+    };
+    for name in mentioned {
+        if !people.iter().any(|person| person.as_str().is_some_and(|p| slugify(p) == slugify(&name))) {
+            people.push(Value::String(name));
+        }
+    }
+    page.insert(PEOPLE.into(), Value::Array(people));
+}
+
+// a taxonomy in front matter: one name or a list, `tags: [audio, hypertext]`
+fn term_names(value: &Value) -> anyhow::Result<Vec<String>> {
+    let name = |value: &Value| match value {
+        Value::String(name) => Ok(name.clone()),
+        Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+        _ => bail!("expected a name or a list of names, found: {}", value),
+    };
+    match value {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(values) => values.iter().map(name).collect(),
+        value => Ok(vec![name(value)?]),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Term {
+    // as first written, e.g. Ada Lovelace
+    pub name: String,
+    pub slug: String,
+    // in source order, as in site.pages
+    pub pages: Vec<Attributes>,
+}
+
+// every term of a taxonomy, by slug, so "Audio" and "audio" are one term
+pub fn terms(taxonomy: &str, pages: &[Attributes]) -> anyhow::Result<Vec<Term>> {
+    let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+    for page in pages {
+        let Some(value) = page.get(taxonomy) else { continue };
+        let source = page.get("source").and_then(Value::as_str).unwrap_or_default();
+        let names = term_names(value)
+            .with_context(|| format!("{}: front matter '{}'", source, taxonomy))?;
+        for name in names {
+            let slug = slugify(&name);
+            if slug.is_empty() {
+                bail!("{}: {} '{}' has nothing to make a url from", source, taxonomy, name);
+            }
+            let term = terms.entry(slug.clone())
+                .or_insert_with(|| Term { name, slug, pages: Vec::new() });
+            if !term.pages.contains(page) {
+                term.pages.push(page.clone());
+            }
+        }
+    }
+    Ok(terms.into_values().collect())
+}
+
+// a generated page: where it goes, the partial for its body and its layout
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listing {
+    pub outpath: PathBuf,
+    template: &'static str,
+    pub layout: String,
+    data: Attributes,
+}
+
+// where a term's page is written, <name>/<slug>.html unless the site-wide
+// permalink moves it like a page
+pub fn term_outpath(config: &Config, taxonomy: &str, slug: &str) -> PathBuf {
+    permalink::generated(config, &Path::new(taxonomy).join(slug).with_extension("html"))
+}
+
+// a page for each term of each taxonomy, and one listing the terms
+pub fn listings(config: &Config, pages: &[Attributes]) -> anyhow::Result<Vec<Listing>> {
+    let mut listings = Vec::new();
+    for (name, taxonomy) in &config.taxonomies {
+        let mut index = Vec::new();
+        for term in terms(name, pages)? {
+            let outpath = term_outpath(config, name, &term.slug);
+            let term = json!({
+                "name": term.name,
+                "slug": term.slug,
+                "url": permalink::url(config, &outpath)?,
+                "count": term.pages.len(),
+                "pages": term.pages,
+            });
+            let mut data = Attributes::new();
+            data.insert("title".into(), term["name"].clone());
+            data.insert("taxonomy".into(), Value::String(name.clone()));
+            data.insert("term".into(), term.clone());
+            listings.push(Listing {
+                outpath,
+                template: TERM_TEMPLATE,
+                layout: taxonomy.layout.clone().unwrap_or_else(|| String::from("default")),
+                data,
+            });
+            index.push(term);
+        }
+        let mut data = Attributes::new();
+        data.insert("title".into(), Value::String(name.clone()));
+        data.insert("taxonomy".into(), Value::String(name.clone()));
+        data.insert("terms".into(), Value::Array(index));
+        listings.push(Listing {
+            outpath: permalink::generated(config, &Path::new(name).join("index.html")),
+            template: INDEX_TEMPLATE,
+            layout: taxonomy.index_layout.clone().unwrap_or_else(|| String::from("default")),
+            data,
+        });
+    }
+    Ok(listings)
+}
+
+// writes every listing, except where a page from sourcedir has the same url,
+// so a site can write its own people/index.md; returns what was written
+pub fn render(context: &Context) -> anyhow::Result<Vec<(Listing, Duration)>> {
+    let config = context.config;
+    let taken: Vec<&Value> = context.pages.iter().filter_map(|page| page.get("url")).collect();
+    let mut written = Vec::new();
+    for listing in listings(config, &context.pages)? {
+        let url = Value::String(permalink::url(config, &listing.outpath)?);
+        if taken.contains(&&url) {
+            continue;
+        }
+        let start = Instant::now();
+        let mut data = output_data(context, Some(&listing.outpath), listing.data.clone())?;
+        let body = context.site.render(&data, |data| context.hbs.render_with_context(listing.template, data))
+            .with_context(|| format!("{}: failed to render {}", listing.outpath.display(), listing.template))?;
+        data.insert("body".into(), Value::String(body));
+        listing.outpath.prepare_output()?;
+        let writer = std::fs::File::create(&listing.outpath)?;
+        context.site.render(&data, |data| context.hbs.render_with_context_to_write(&listing.layout, data, writer))
+            .with_context(|| format!("{}: failed to render layout {}", listing.outpath.display(), listing.layout))?;
+        written.push((listing, start.elapsed()));
+    }
+    Ok(written)
+}
+
+// listings of an earlier set of pages that the current one doesn't have
+pub fn remove_stale(config: &Config, before: &[Attributes], after: &[Attributes]) -> anyhow::Result<()> {
+    let current: Vec<PathBuf> = listings(config, after)?.into_iter().map(|listing| listing.outpath).collect();
+    for listing in listings(config, before)? {
+        if !current.contains(&listing.outpath) && listing.outpath.is_file() {
+            std::fs::remove_file(&listing.outpath)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::parse_yaml_attributes;
+
+    fn page(yaml: &str) -> Attributes {
+        parse_yaml_attributes(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_person_names() {
+        let text = r#"{{ person "Ada Lovelace" }} and {{person 'Ted Nelson'}}, {{ persons "x" }}"#;
+        assert_eq!(person_names(text), vec!["Ada Lovelace", "Ted Nelson"]);
+
+        let mut attr = page("people: Ted Nelson");
+        add_people(&mut attr, text);
+        assert_eq!(attr[PEOPLE], json!(["Ted Nelson", "Ada Lovelace"]));
+    }
+
+    #[test]
+    fn test_terms() {
+        let pages = vec![
+            page("source: a.md\ntags: [Audio, hypertext]"),
+            page("source: b.md\ntags: audio"),
+            page("source: c.md"),
+        ];
+        let terms = terms("tags", &pages).unwrap();
+        let summary: Vec<(&str, &str, usize)> = terms.iter()
+            .map(|term| (term.name.as_str(), term.slug.as_str(), term.pages.len()))
+            .collect();
+        assert_eq!(summary, vec![("Audio", "audio", 2), ("hypertext", "hypertext", 1)]);
+        assert!(super::terms("tags", &[page("source: d.md\ntags: {a: b}")]).is_err());
+    }
+
+    #[test]
+    // term pages follow the site-wide permalink, the index keeps its place
+    fn test_term_outpath_permalink() {
+        let mut config = Config::default();
+        config.taxonomies.insert(String::from("tags"), Default::default());
+        let pages = vec![page("source: a.md\ntags: Audio")];
+        let outpaths = |config: &Config| -> Vec<PathBuf> {
+            listings(config, &pages).unwrap().into_iter().map(|listing| listing.outpath).collect()
+        };
+        assert_eq!(outpaths(&config), vec![
+            config.outdir.join("tags/audio.html"),
+            config.outdir.join("tags/index.html"),
+        ]);
+        config.permalink = Some(String::from("/:section/:slug/"));
+        assert_eq!(outpaths(&config), vec![
+            config.outdir.join("tags/audio/index.html"),
+            config.outdir.join("tags/index.html"),
+        ]);
+        // a pattern the term page has no front matter for leaves it in place
+        config.permalink = Some(String::from("/:year/:slug/"));
+        assert_eq!(term_outpath(&config, "tags", "audio"), config.outdir.join("tags/audio.html"));
+    }
+}
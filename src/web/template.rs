@@ -1,14 +1,22 @@
 use anyhow::anyhow;
-use handlebars::{Handlebars,handlebars_helper, Helper,
+use handlebars::{Handlebars,handlebars_helper, Helper, HelperDef,
                 Context as HandlebarsContext, Output, RenderContext,
-                RenderError, RenderErrorReason};
+                RenderError, RenderErrorReason, ScopedJson};
 use ::slug::slugify;
-use tracing::info;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, info, trace};
+use walkdir::WalkDir;
 
 use crate::{
-    config::{Config, Context},
+    config::{ref_partial_dir, Config, Context},
+    theme,
     util::*,
-    web,
+    web::{self, diagnostic::Diagnostics, document::{Outpaths, SiteData}},
 };
 
 handlebars_helper!(slug: |input:String|
@@ -18,64 +26,207 @@ handlebars_helper!(split: |input:String, {sep:str="\n"}|
         input.split(sep).collect::<Vec<&str>>()
 );
 
-fn helper_context_get_string_from_key(hc: &HandlebarsContext, key: &str) -> String {
-    if let Some(map) = hc.data().as_object() {
-        if let Some(value) = map.get(key) {
-            if let Some(s) = value.as_str() {
-                return s.to_string()
-            }
-        }
+// pointer is a json pointer into the context data, e.g. "/site/baseurl"
+fn helper_context_get_string(hc: &HandlebarsContext, pointer: &str) -> Option<String> {
+    hc.data()
+        .pointer(pointer)
+        .and_then(|value| value.as_str())
+        .map(|s| s.to_string())
+}
+// {{ person "Ada Lovelace" }}: a link to the person's page, the term page
+// of the people taxonomy when the site has one, with prefix
+struct PersonHelper {
+    config: Config,
+}
+
+impl HelperDef for PersonHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        hc: &'rc HandlebarsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> Result<(), RenderError> {
+        let param0 = h
+                .param(0)
+                .ok_or(RenderErrorReason::ParamNotFoundForIndex("name", 0))?;
+        let name = param0.value().as_str()
+            .ok_or(RenderErrorReason::InvalidParamType("person expects a name"))?;
+        // site attribute, unless the page sets its own
+        let baseurl = helper_context_get_string(hc, "/baseurl")
+            .or_else(|| helper_context_get_string(hc, "/site/baseurl"))
+            .unwrap_or_default();
+        let output = match self.config.taxonomies.contains_key(web::taxonomy::PEOPLE) {
+            true => {
+                let outpath = web::taxonomy::term_outpath(&self.config, web::taxonomy::PEOPLE, &slugify(name));
+                let url = web::permalink::url(&self.config, &outpath)
+                    .map_err(|e| RenderErrorReason::Other(format!("person \"{}\": {}", name, e)))?;
+                format!("<a href='{}{}'>{}</a>", baseurl.trim_end_matches('/'), url, name)
+            },
+            false => format!("<a href='{}/people/{}'>{}</a>", baseurl, slugify(name), name),
+        };
+        out.write(&output)?;
+
+        Ok(())
     }
-    String::from("")
 }
-fn person_helper(
-    h: &Helper<'_>,
-    _: &Handlebars,
-    hc: &HandlebarsContext,
-    _: &mut RenderContext,
-    out: &mut dyn Output,
-) -> Result<(), RenderError> {
-    let param0 = h
-            .param(0)
-            .ok_or(RenderErrorReason::ParamNotFoundForIndex("name", 0))?;
-    let name = param0.value().as_str().unwrap();
-    let baseurl = helper_context_get_string_from_key(hc, "baseurl".into());
-    let output = format!("<a href='{}/people/{}'>{}</a>", baseurl, slugify(name), name);
-    out.write(&output)?;
 
-    Ok(())
+// {{ url "blog/post.md" }}: where a source file is published, with prefix and
+// permalink applied; an anchor is kept, {{ url "blog/post.md#notes" }};
+// without a path it is the `url` in scope, e.g. the page's own or a nav item's
+struct UrlHelper {
+    config: Config,
+    outpaths: Outpaths,
+}
+
+impl HelperDef for UrlHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        hc: &'rc HandlebarsContext,
+        rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let Some(param) = h.param(0) else {
+            return rc.evaluate(hc, "url");
+        };
+        let path = param.value().as_str()
+            .ok_or(RenderErrorReason::InvalidParamType("url expects a source path"))?;
+        let (path, anchor) = match path.split_once('#') {
+            Some((path, anchor)) => (path, format!("#{}", anchor)),
+            None => (path, String::new()),
+        };
+        let url = web::permalink::source_url(&self.config, &self.outpaths, path)
+            .map_err(|e| RenderErrorReason::Other(format!("url \"{}\": {}", path, e)))?;
+        Ok(ScopedJson::Derived(Value::String(url + &anchor)))
+    }
 }
 
+// built-in theme first, then each templatedir layer in order,
+// so later layers replace files with the same relative path
+fn copy_template_layers(config: &Config, buildtemplatedir: &Path) -> anyhow::Result<()> {
+    let mut layer_of: BTreeMap<PathBuf, String> = theme::files().iter()
+        .map(|(rel_path, _)| (PathBuf::from(rel_path), String::from("built-in theme")))
+        .collect();
+    theme::write_to(buildtemplatedir, true)?;
 
+    for layer in &config.templatedirs {
+        copy_dir_all(layer, buildtemplatedir)?;
+        for entry in WalkDir::new(layer).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                let rel_path = entry.path().strip_prefix(layer)?;
+                layer_of.insert(rel_path.to_path_buf(), layer.display().to_string());
+            }
+        }
+    }
 
-pub fn init<'a>(config: &'a Config) -> anyhow::Result<Context<'a>> {
+    for (rel_path, layer) in &layer_of {
+        if rel_path.extension() == Some(OsStr::new("hbs")) {
+            debug!("template {} from {}", rel_path.display(), layer);
+        } else {
+            trace!("template asset {} from {}", rel_path.display(), layer);
+        }
+    }
+    Ok(())
+}
+
+// a ref that fails is recorded in diagnostics, the build goes on without it
+pub fn init<'a>(config: &'a Config, diagnostics: &mut Diagnostics) -> anyhow::Result<Context<'a>> {
     info!("init_templates");
-    clean_and_recreate_dir(&config.builddir)?;
+    // the rest of builddir is kept, it holds the build cache;
+    // so are generated ref partials, only refs that changed are written again
     let buildtemplatedir = config.buildtemplatedir();
-    copy_dir_all(&config.templatedir, &buildtemplatedir)?;
-    let buildrefdir = buildtemplatedir.join("ref");
+    if config.cache {
+        std::fs::create_dir_all(&buildtemplatedir)?;
+        refresh_layers(config)?;
+    } else {
+        clean_and_recreate_dir(&buildtemplatedir)?;
+        copy_template_layers(config, &buildtemplatedir)?;
+    }
+    for (namespace, refdir) in config.ref_roots() {
+        let buildrefdir = create_buildrefdir(config, namespace)?;
+        diagnostics.check(web::Ref::process_markdown(config, refdir, namespace, &buildrefdir))?;
+    }
+
+    let hbs = register(config)?;
+    Ok(Context {
+        config, hbs, pages: Vec::new(), site: SiteData::new(config, &[]), outpaths: Outpaths::default()
+    })
+}
+
+// once the build knows where each source is written, the url helper does too
+pub fn set_outpaths(context: &mut Context, outpaths: Outpaths) {
+    let helper = UrlHelper { config: context.config.clone(), outpaths: outpaths.clone() };
+    context.hbs.register_helper("url", Box::new(helper));
+    context.outpaths = outpaths;
+}
+
+// templates registered for a build with another outdir, i.e. staging:
+// the helpers that hold a config get this context's
+pub fn rebind(context: &mut Context, outpaths: Outpaths) {
+    context.hbs.register_helper("person", Box::new(PersonHelper { config: context.config.clone() }));
+    set_outpaths(context, outpaths);
+}
+
+fn create_buildrefdir(config: &Config, namespace: Option<&str>) -> anyhow::Result<PathBuf> {
+    let buildrefdir = config.buildtemplatedir().join(ref_partial_dir(namespace));
     std::fs::create_dir_all(&buildrefdir).map_err(|e| {
         anyhow!(format!("failed to create directory: {}, error: {}", &buildrefdir.display(), e))
     })?;
+    Ok(buildrefdir.canonicalize()?)
+}
 
-    let ref_dir = config.sourcedir.canonicalize()?.parent().unwrap().join("ref");
-    web::Ref::process_markdown(config, ref_dir, &buildtemplatedir.canonicalize()?.join("ref"))?;
+// after a change in a templatedir layer: copy the layers again,
+// keeping generated ref partials, so deleted templates don't linger
+pub fn refresh_layers(config: &Config) -> anyhow::Result<()> {
+    let buildtemplatedir = config.buildtemplatedir();
+    let generated: Vec<PathBuf> = config.ref_roots().iter()
+        .map(|(namespace, _)| buildtemplatedir.join(ref_partial_dir(*namespace)))
+        .collect();
+    remove_all_except(&buildtemplatedir, &generated)?;
+    copy_template_layers(config, &buildtemplatedir)
+}
 
+// everything in dir but the kept paths, and the directories that lead to them
+fn remove_all_except(dir: &Path, keep: &[PathBuf]) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if keep.contains(&path) {
+            continue;
+        }
+        if keep.iter().any(|kept| kept.starts_with(&path)) {
+            remove_all_except(&path, keep)?;
+        } else if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// after a change in a ref directory: regenerate only the affected ref
+pub fn refresh_ref(config: &Config, namespace: Option<&str>, refdir: &Path, changed: &Path) -> anyhow::Result<()> {
+    let buildrefdir = create_buildrefdir(config, namespace)?;
+    web::Ref::process_changed(config, refdir, namespace, &buildrefdir, changed)
+}
+
+// templates and helpers, from the build template directory
+pub fn register(config: &Config) -> anyhow::Result<Handlebars<'static>> {
     let buildtemplatedir = config.buildtemplatedir();
     info!("buildtemplatedir: {}", buildtemplatedir.display());
     let mut hbs = Handlebars::new();
-    hbs.register_helper("person", Box::new(person_helper));
+    hbs.register_helper("person", Box::new(PersonHelper { config: config.clone() }));
     hbs.register_helper("slug", Box::new(slug));
     hbs.register_helper("split", Box::new(split));
+    hbs.register_helper("url", Box::new(UrlHelper { config: config.clone(), outpaths: Outpaths::default() }));
     hbs.register_templates_directory(&buildtemplatedir, Default::default())
         .map_err(|e| {
             anyhow!("failed to register template directory, error {:?}. directory: {}", e, buildtemplatedir.display())
         })?;
     info!("Setup: template directory '{}' registered", &buildtemplatedir.display());
-
-    Ok(Context {
-        config, hbs
-    })
+    Ok(hbs)
 }
 
 #[cfg(test)]
@@ -86,7 +237,7 @@ mod tests {
     #[test]
     fn test_person_helper() {
         let mut hbs = Handlebars::new();
-        hbs.register_helper("person", Box::new(person_helper));
+        hbs.register_helper("person", Box::new(PersonHelper { config: Config::default() }));
         let mut data: HashMap<String, String> = HashMap::new();
         data.insert(String::from("baseurl"),
                     String::from("https://example.com"));
@@ -96,6 +247,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_person_helper_site_baseurl() {
+        let mut hbs = Handlebars::new();
+        hbs.register_helper("person", Box::new(PersonHelper { config: Config::default() }));
+        let data = serde_json::json!({"site": {"baseurl": "https://example.com"}});
+        assert_eq!(
+            hbs.render_template("{{ person \"Ada Lovelace\" }}", &data).unwrap(),
+            "<a href='https://example.com/people/ada-lovelace'>Ada Lovelace</a>"
+        );
+    }
+
+    #[test]
+    // with a people taxonomy, the link goes to the term page, under the prefix
+    fn test_person_helper_taxonomy() {
+        let mut hbs = Handlebars::new();
+        let mut config = Config { prefix: String::from("/blog/"), ..Config::default() };
+        config.taxonomies.insert(String::from("people"), Default::default());
+        hbs.register_helper("person", Box::new(PersonHelper { config: config.clone() }));
+        assert_eq!(
+            hbs.render_template("{{ person \"Ada Lovelace\" }}", &serde_json::json!({})).unwrap(),
+            "<a href='/blog/people/ada-lovelace.html'>Ada Lovelace</a>"
+        );
+        config.permalink = Some(String::from("/:section/:slug/"));
+        hbs.register_helper("person", Box::new(PersonHelper { config }));
+        assert_eq!(
+            hbs.render_template("{{ person \"Ada Lovelace\" }}", &serde_json::json!({})).unwrap(),
+            "<a href='/blog/people/ada-lovelace/'>Ada Lovelace</a>"
+        );
+        assert!(hbs.render_template("{{ person 3 }}", &serde_json::json!({})).is_err());
+    }
+
     #[test]
     fn test_split_helper() {
         let mut hbs = Handlebars::new();
@@ -125,6 +307,178 @@ Second Line");
         );
     }
 
+    #[test]
+    fn test_url_helper() {
+        let dir = std::env::temp_dir().join("altwebgen-url-helper");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("source/blog/post.md", "---\npermalink: /posts/first/\n---\n# First"),
+            ("source/about.md", "# About"),
+        ] {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .prefix("/site/")
+            .build().unwrap();
+        let mut hbs = Handlebars::new();
+        hbs.register_helper("url", Box::new(UrlHelper { config, outpaths: Outpaths::default() }));
+        let data = serde_json::json!({});
+        assert_eq!(hbs.render_template("{{ url \"blog/post.md#notes\" }} {{ url \"/about.md\" }}", &data).unwrap(),
+            "/site/posts/first/#notes /site/about.html");
+        assert!(hbs.render_template("{{ url \"missing.md\" }}", &data).is_err());
+        let data = serde_json::json!({"url": "/site/", "nav": [{"url": "a.html"}]});
+        assert_eq!(hbs.render_template("{{ url }} {{#each nav}}{{ url }}{{/each}}", &data).unwrap(),
+            "/site/ a.html");
+    }
 
-}
+    #[test]
+    // later template layers shadow earlier ones, for templates and assets
+    #[allow(deprecated)]
+    fn test_template_layers() {
+        use crate::{config::*, setup};
+        let dir = std::env::temp_dir().join("altwebgen-template-layers");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("source/index.md.hbs", "{{> _note }}"),
+            ("theme/default.hbs", "theme layout: {{{ body }}}"),
+            ("theme/_note.hbs", "theme note"),
+            ("theme/style.css", "theme css"),
+            ("theme/extra.css", "theme extra"),
+            ("template/_note.hbs", "site note"),
+            ("template/style.css", "site css"),
+        ] {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let mut config = Config::new(&dir.join(".dist").to_string_lossy(),
+                                     &dir.join("source").to_string_lossy(),
+                                     &dir.join("template").to_string_lossy(),
+                                     "", Mode::Build, Transcript::Off);
+        config.templatedirs = vec![dir.join("theme"), dir.join("template")];
+        setup::init_and_build(&config).unwrap();
+
+        let read = |rel_path: &str| std::fs::read_to_string(dir.join(".dist").join(rel_path)).unwrap();
+        assert_eq!(read("index.html"), "theme layout: <p>site note</p>\n");
+        assert_eq!(read("style.css"), "site css");
+        assert_eq!(read("extra.css"), "theme extra");
+    }
 
+    #[test]
+    // each ref root has its own namespace for partials and media
+    fn test_ref_roots() {
+        use crate::{config::*, setup};
+        let dir = std::env::temp_dir().join("altwebgen-ref-roots");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("source/index.md.hbs", "{{> ref/local.html }}{{> archive/ref/quote.html }}"),
+            ("template/default.hbs", "{{{ body }}}"),
+            ("ref/local.md", "local ref"),
+            ("archive/quote.md", "archived quote"),
+        ] {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        std::fs::copy("src/test/data/short-sentence.mp3", dir.join("archive/quote.mp3")).unwrap();
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .refroot("archive", dir.join("archive"))
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+
+        let html = std::fs::read_to_string(dir.join(".dist/index.html")).unwrap();
+        assert!(html.contains("<p>local ref</p>"));
+        assert!(html.contains("<p>archived quote</p>"));
+        assert!(html.contains("src=\"/media/archive/quote.mp3\""));
+        assert!(dir.join(".dist/media/archive/quote.mp3").exists());
+        assert!(!dir.join(".dist/archive").exists());
+    }
+
+    #[test]
+    // a namespace can share its name with a theme or template directory:
+    // only <namespace>/ref holds generated partials
+    fn test_ref_root_named_theme() {
+        use crate::{config::*, setup};
+        let dir = std::env::temp_dir().join("altwebgen-ref-root-theme");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("source/index.md.hbs", "{{> theme/ref/quote.html }}"),
+            ("template/theme/site.css", "site css"),
+            ("shared/quote.md", "shared quote"),
+        ] {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let config = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .refroot("theme", dir.join("shared"))
+            .transcript(Transcript::Off)
+            .build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        assert!(std::fs::read_to_string(dir.join(".dist/index.html")).unwrap().contains("<p>shared quote</p>"));
+        assert!(dir.join(".dist/theme/audio.css").exists());
+        assert!(dir.join(".dist/theme/site.css").exists());
+        assert!(!dir.join(".dist/theme/ref").exists());
+
+        std::fs::remove_file(dir.join("template/theme/site.css")).unwrap();
+        refresh_layers(&config).unwrap();
+        let buildtheme = config.buildtemplatedir().join("theme");
+        assert!(buildtheme.join("ref/quote.html.hbs").exists());
+        assert!(buildtheme.join("audio.css").exists());
+        assert!(!buildtheme.join("site.css").exists());
+    }
+
+    #[test]
+    // a ref's partial is only written again when its files or the settings change
+    fn test_unchanged_refs_kept() {
+        use crate::{config::*, setup};
+        let dir = std::env::temp_dir().join("altwebgen-ref-cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (rel_path, contents) in [
+            ("source/index.md.hbs", "{{> ref/quote.html }}"),
+            ("ref/quote.md", "a quote"),
+            ("ref/gone.md", "soon gone"),
+        ] {
+            let path = dir.join(rel_path);
+            path.create_all_parent_dir().unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let builder = Config::builder()
+            .outdir(dir.join(".dist"))
+            .sourcedir(dir.join("source"))
+            .templatedirs(vec![dir.join("template")])
+            .transcript(Transcript::Off);
+        let config = builder.clone().build().unwrap();
+        setup::init_and_build(&config).unwrap();
+        let partial = config.buildtemplatedir().join("ref/quote.html.hbs");
+        let gone = config.buildtemplatedir().join("ref/gone.html.hbs");
+        assert!(gone.is_file());
+
+        // stands in for a partial that took long to align
+        std::fs::write(&partial, "kept").unwrap();
+        std::fs::remove_file(dir.join("ref/gone.md")).unwrap();
+        setup::clean_build(&config).unwrap();
+        assert_eq!(std::fs::read_to_string(&partial).unwrap(), "kept");
+        assert!(!gone.exists());
+
+        let config = builder.transcript(Transcript::Static).build().unwrap();
+        setup::clean_build(&config).unwrap();
+        assert!(std::fs::read_to_string(&partial).unwrap().contains("<p>a quote</p>"));
+
+        std::fs::write(&partial, "kept").unwrap();
+        std::fs::write(dir.join("ref/quote.md"), "another quote").unwrap();
+        setup::clean_build(&config).unwrap();
+        assert!(std::fs::read_to_string(&partial).unwrap().contains("<p>another quote</p>"));
+    }
+}